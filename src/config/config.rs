@@ -5,7 +5,8 @@
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
 use std::collections::BTreeMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use url_serde::SerdeUrl;
 
 use super::defaults;
@@ -18,6 +19,11 @@ pub struct Config {
     pub dns: ConfigDNS,
     pub geo: ConfigGeo,
     pub http: ConfigHTTP,
+
+    #[serde(default = "defaults::store")]
+    pub store: ConfigStore,
+
+    #[serde(default = "defaults::redis")]
     pub redis: ConfigRedis,
 }
 
@@ -28,6 +34,9 @@ pub struct ConfigServer {
 
     #[serde(default = "defaults::server_identifier")]
     pub identifier: String,
+
+    #[serde(default = "defaults::server_version")]
+    pub version: String,
 }
 
 #[derive(Deserialize)]
@@ -58,6 +67,21 @@ pub struct ConfigDNS {
     #[serde(default = "defaults::dns_record_ttl")]
     pub record_ttl: u32,
 
+    #[serde(default = "defaults::dns_negative_ttl")]
+    pub negative_ttl: u32,
+
+    #[serde(default = "defaults::dns_minimal_responses")]
+    pub minimal_responses: bool,
+
+    #[serde(default = "defaults::dns_any_response")]
+    pub any_response: ConfigDNSAnyResponse,
+
+    #[serde(default = "defaults::dns_chaos_enable")]
+    pub chaos_enable: bool,
+
+    #[serde(default = "defaults::dns_nsid_enable")]
+    pub nsid_enable: bool,
+
     #[serde(default = "defaults::dns_zone")]
     pub zone: BTreeMap<String, ConfigDNSZone>,
 
@@ -65,8 +89,29 @@ pub struct ConfigDNS {
     pub health: ConfigDNSHealth,
 }
 
+#[derive(Deserialize, PartialEq)]
+pub enum ConfigDNSAnyResponse {
+    #[serde(rename = "hinfo")]
+    HINFO,
+
+    #[serde(rename = "rrset")]
+    RRSet,
+}
+
 #[derive(Deserialize)]
-pub struct ConfigDNSZone {}
+pub struct ConfigDNSZone {
+    pub nameservers: Option<Vec<String>>,
+
+    pub soa_master: Option<String>,
+    pub soa_responsible: Option<String>,
+    pub soa_refresh: Option<i32>,
+    pub soa_retry: Option<i32>,
+    pub soa_expire: Option<i32>,
+    pub soa_ttl: Option<u32>,
+
+    pub record_ttl: Option<u32>,
+    pub negative_ttl: Option<u32>,
+}
 
 #[derive(Default, Deserialize)]
 pub struct ConfigDNSHealth {
@@ -153,7 +198,148 @@ pub struct ConfigHTTP {
     #[serde(default = "defaults::http_workers")]
     pub workers: u16,
 
-    pub record_token: String,
+    pub record_token: Option<String>,
+    pub record_token_hash: Option<String>,
+
+    #[serde(default = "defaults::http_auth_max_attempts")]
+    pub auth_max_attempts: u32,
+
+    #[serde(default = "defaults::http_auth_lockout_window")]
+    pub auth_lockout_window: u64,
+
+    #[serde(default = "defaults::http_trusted_proxies")]
+    pub trusted_proxies: Vec<IpAddr>,
+
+    #[serde(default = "defaults::http_tokens")]
+    pub tokens: Vec<ConfigHTTPToken>,
+
+    #[serde(default = "defaults::http_audit")]
+    pub audit: ConfigHTTPAudit,
+
+    #[serde(default = "defaults::http_webhook")]
+    pub webhook: ConfigHTTPWebhook,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigHTTPToken {
+    pub name: String,
+    pub token: Option<String>,
+    pub token_hash: Option<String>,
+    pub zones: Option<Vec<ZoneName>>,
+    pub names: Option<Vec<String>>,
+
+    #[serde(default = "defaults::http_tokens_operations")]
+    pub operations: Vec<ConfigHTTPTokenOperation>,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy)]
+pub enum ConfigHTTPTokenOperation {
+    #[serde(rename = "read")]
+    Read,
+
+    #[serde(rename = "write")]
+    Write,
+
+    #[serde(rename = "delete")]
+    Delete,
+
+    #[serde(rename = "metrics")]
+    Metrics,
+
+    #[serde(rename = "audit")]
+    Audit,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigHTTPAudit {
+    #[serde(default = "defaults::http_audit_file_enable")]
+    pub file_enable: bool,
+
+    #[serde(default = "defaults::http_audit_file_path")]
+    pub file_path: PathBuf,
+
+    #[serde(default = "defaults::http_audit_redis_enable")]
+    pub redis_enable: bool,
+
+    #[serde(default = "defaults::http_audit_redis_stream")]
+    pub redis_stream: String,
+
+    #[serde(default = "defaults::http_audit_redis_stream_max_length")]
+    pub redis_stream_max_length: usize,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigHTTPWebhook {
+    #[serde(default = "defaults::http_webhook_spool_path")]
+    pub spool_path: PathBuf,
+
+    #[serde(default = "defaults::http_webhook_timeout")]
+    pub timeout: u64,
+
+    #[serde(default = "defaults::http_webhook_retry_attempts")]
+    pub retry_attempts: u32,
+
+    #[serde(default = "defaults::http_webhook_retry_backoff")]
+    pub retry_backoff: u64,
+
+    #[serde(default = "defaults::http_webhook_retry_backoff_maximum")]
+    pub retry_backoff_maximum: u64,
+
+    #[serde(default = "defaults::http_webhook_endpoints")]
+    pub endpoints: Vec<ConfigHTTPWebhookEndpoint>,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigHTTPWebhookEndpoint {
+    pub name: Option<String>,
+    pub url: SerdeUrl,
+    pub secret: String,
+    pub zones: Option<Vec<ZoneName>>,
+
+    #[serde(default = "defaults::http_webhook_endpoints_events")]
+    pub events: Vec<ConfigHTTPWebhookEvent>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum ConfigHTTPWebhookEvent {
+    #[serde(rename = "create")]
+    Create,
+
+    #[serde(rename = "update")]
+    Update,
+
+    #[serde(rename = "delete")]
+    Delete,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigStore {
+    #[serde(default = "defaults::store_backend")]
+    pub backend: ConfigStoreBackend,
+
+    #[serde(default = "defaults::store_history_limit")]
+    pub history_limit: usize,
+
+    #[serde(default = "defaults::store_embedded")]
+    pub embedded: ConfigStoreEmbedded,
+}
+
+#[derive(Deserialize, PartialEq)]
+pub enum ConfigStoreBackend {
+    #[serde(rename = "redis")]
+    Redis,
+
+    #[serde(rename = "embedded")]
+    Embedded,
+
+    #[serde(rename = "memory")]
+    Memory,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigStoreEmbedded {
+    #[serde(default = "defaults::store_embedded_path")]
+    pub path: PathBuf,
 }
 
 #[derive(Deserialize)]
@@ -179,35 +365,178 @@ pub struct ConfigRedis {
     #[serde(default = "defaults::redis_cache_expire_seconds")]
     pub cache_expire_seconds: u64,
 
+    #[serde(default = "defaults::redis_cache_max_entries")]
+    pub cache_max_entries: usize,
+
+    #[serde(default = "defaults::redis_cache_max_negative_entries")]
+    pub cache_max_negative_entries: usize,
+
+    #[serde(default = "defaults::redis_stale_max_seconds")]
+    pub stale_max_seconds: u64,
+
+    #[serde(default = "defaults::redis_stale_ttl")]
+    pub stale_ttl: u32,
+
+    #[serde(default = "defaults::redis_warmup_enable")]
+    pub warmup_enable: bool,
+
+    #[serde(default = "defaults::redis_warmup_timeout_seconds")]
+    pub warmup_timeout_seconds: u64,
+
+    #[serde(default = "defaults::redis_invalidate_enable")]
+    pub invalidate_enable: bool,
+
+    #[serde(default = "defaults::redis_invalidate_channel")]
+    pub invalidate_channel: String,
+
     #[serde(default = "defaults::redis_master")]
     pub master: ConfigRedisMaster,
 
     pub rescue: Option<Vec<ConfigRedisRescue>>,
+
+    pub sentinel: Option<ConfigRedisSentinel>,
+
+    pub cluster: Option<ConfigRedisCluster>,
 }
 
 #[derive(Default, Deserialize)]
 pub struct ConfigRedisMaster {
+    pub url: Option<String>,
+
     #[serde(default = "defaults::redis_master_host")]
     pub host: String,
 
     #[serde(default = "defaults::redis_master_port")]
     pub port: u16,
 
+    pub socket_path: Option<PathBuf>,
+
+    #[serde(default = "defaults::redis_master_tls")]
+    pub tls: bool,
+
+    pub ca_file: Option<PathBuf>,
+    pub username: Option<String>,
     pub password: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct ConfigRedisRescue {
+    pub url: Option<String>,
+
+    #[serde(default = "defaults::redis_rescue_host")]
     pub host: String,
 
     #[serde(default = "defaults::redis_rescue_port")]
     pub port: u16,
 
+    pub socket_path: Option<PathBuf>,
+
+    #[serde(default = "defaults::redis_rescue_tls")]
+    pub tls: bool,
+
+    pub ca_file: Option<PathBuf>,
+    pub username: Option<String>,
     pub password: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct ConfigRedisSentinel {
+    pub addresses: Vec<String>,
+    pub master_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfigRedisCluster {
+    pub nodes: Vec<String>,
+}
+
+impl ConfigHTTPTokenOperation {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            ConfigHTTPTokenOperation::Read => "read",
+            ConfigHTTPTokenOperation::Write => "write",
+            ConfigHTTPTokenOperation::Delete => "delete",
+            ConfigHTTPTokenOperation::Metrics => "metrics",
+            ConfigHTTPTokenOperation::Audit => "audit",
+        }
+    }
+}
+
+impl ConfigHTTPWebhookEvent {
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            ConfigHTTPWebhookEvent::Create => "create",
+            ConfigHTTPWebhookEvent::Update => "update",
+            ConfigHTTPWebhookEvent::Delete => "delete",
+        }
+    }
+}
+
 impl ConfigDNS {
     pub fn zone_exists(&self, name: &str) -> bool {
         self.zone.contains_key(name)
     }
+
+    pub fn zone_nameservers(&self, name: &str) -> &Vec<String> {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.nameservers.as_ref())
+            .unwrap_or(&self.nameservers)
+    }
+
+    pub fn zone_soa_master(&self, name: &str) -> &str {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_master.as_ref())
+            .unwrap_or(&self.soa_master)
+    }
+
+    pub fn zone_soa_responsible(&self, name: &str) -> &str {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_responsible.as_ref())
+            .unwrap_or(&self.soa_responsible)
+    }
+
+    pub fn zone_soa_refresh(&self, name: &str) -> i32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_refresh)
+            .unwrap_or(self.soa_refresh)
+    }
+
+    pub fn zone_soa_retry(&self, name: &str) -> i32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_retry)
+            .unwrap_or(self.soa_retry)
+    }
+
+    pub fn zone_soa_expire(&self, name: &str) -> i32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_expire)
+            .unwrap_or(self.soa_expire)
+    }
+
+    pub fn zone_soa_ttl(&self, name: &str) -> u32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.soa_ttl)
+            .unwrap_or(self.soa_ttl)
+    }
+
+    pub fn zone_record_ttl(&self, name: &str) -> u32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.record_ttl)
+            .unwrap_or(self.record_ttl)
+    }
+
+    pub fn zone_negative_ttl(&self, name: &str) -> u32 {
+        self.zone
+            .get(name)
+            .and_then(|zone| zone.negative_ttl)
+            .unwrap_or(self.negative_ttl)
+    }
 }
@@ -5,11 +5,14 @@
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
 use std::collections::BTreeMap;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 
 use super::config::{
-    ConfigDNSHealth, ConfigDNSHealthHTTP, ConfigDNSHealthHTTPMethod, ConfigDNSHealthNotify,
-    ConfigDNSZone, ConfigRedisMaster,
+    ConfigDNSAnyResponse, ConfigDNSHealth, ConfigDNSHealthHTTP, ConfigDNSHealthHTTPMethod,
+    ConfigDNSHealthNotify, ConfigDNSZone, ConfigHTTPAudit, ConfigHTTPToken,
+    ConfigHTTPTokenOperation, ConfigHTTPWebhook, ConfigHTTPWebhookEndpoint, ConfigHTTPWebhookEvent,
+    ConfigRedis, ConfigRedisMaster, ConfigStore, ConfigStoreBackend, ConfigStoreEmbedded,
 };
 
 pub fn server_log_level() -> String {
@@ -20,6 +23,10 @@ pub fn server_identifier() -> String {
     "constellation/0".to_string()
 }
 
+pub fn server_version() -> String {
+    format!("constellation {}", env!("CARGO_PKG_VERSION"))
+}
+
 pub fn dns_inets() -> Vec<SocketAddr> {
     vec!["0.0.0.0:53".parse().unwrap(), "[::]:53".parse().unwrap()]
 }
@@ -48,6 +55,26 @@ pub fn dns_record_ttl() -> u32 {
     3600
 }
 
+pub fn dns_negative_ttl() -> u32 {
+    60
+}
+
+pub fn dns_minimal_responses() -> bool {
+    false
+}
+
+pub fn dns_any_response() -> ConfigDNSAnyResponse {
+    ConfigDNSAnyResponse::HINFO
+}
+
+pub fn dns_chaos_enable() -> bool {
+    true
+}
+
+pub fn dns_nsid_enable() -> bool {
+    true
+}
+
 pub fn dns_zone() -> BTreeMap<String, ConfigDNSZone> {
     BTreeMap::new()
 }
@@ -131,6 +158,149 @@ pub fn http_workers() -> u16 {
     2
 }
 
+pub fn http_auth_max_attempts() -> u32 {
+    10
+}
+
+pub fn http_auth_lockout_window() -> u64 {
+    300
+}
+
+pub fn http_trusted_proxies() -> Vec<IpAddr> {
+    Vec::new()
+}
+
+pub fn http_tokens() -> Vec<ConfigHTTPToken> {
+    Vec::new()
+}
+
+pub fn http_tokens_operations() -> Vec<ConfigHTTPTokenOperation> {
+    vec![ConfigHTTPTokenOperation::Read]
+}
+
+pub fn http_audit() -> ConfigHTTPAudit {
+    ConfigHTTPAudit {
+        file_enable: http_audit_file_enable(),
+        file_path: http_audit_file_path(),
+        redis_enable: http_audit_redis_enable(),
+        redis_stream: http_audit_redis_stream(),
+        redis_stream_max_length: http_audit_redis_stream_max_length(),
+    }
+}
+
+pub fn http_audit_file_enable() -> bool {
+    false
+}
+
+pub fn http_audit_file_path() -> PathBuf {
+    PathBuf::from("./data/audit/audit.log")
+}
+
+pub fn http_audit_redis_enable() -> bool {
+    false
+}
+
+pub fn http_audit_redis_stream() -> String {
+    "cl:audit".to_string()
+}
+
+pub fn http_audit_redis_stream_max_length() -> usize {
+    100000
+}
+
+pub fn http_webhook() -> ConfigHTTPWebhook {
+    ConfigHTTPWebhook {
+        spool_path: http_webhook_spool_path(),
+        timeout: http_webhook_timeout(),
+        retry_attempts: http_webhook_retry_attempts(),
+        retry_backoff: http_webhook_retry_backoff(),
+        retry_backoff_maximum: http_webhook_retry_backoff_maximum(),
+        endpoints: http_webhook_endpoints(),
+    }
+}
+
+pub fn http_webhook_spool_path() -> PathBuf {
+    PathBuf::from("./data/webhook/")
+}
+
+pub fn http_webhook_timeout() -> u64 {
+    10
+}
+
+pub fn http_webhook_retry_attempts() -> u32 {
+    10
+}
+
+pub fn http_webhook_retry_backoff() -> u64 {
+    10
+}
+
+pub fn http_webhook_retry_backoff_maximum() -> u64 {
+    3600
+}
+
+pub fn http_webhook_endpoints() -> Vec<ConfigHTTPWebhookEndpoint> {
+    Vec::new()
+}
+
+pub fn http_webhook_endpoints_events() -> Vec<ConfigHTTPWebhookEvent> {
+    vec![
+        ConfigHTTPWebhookEvent::Create,
+        ConfigHTTPWebhookEvent::Update,
+        ConfigHTTPWebhookEvent::Delete,
+    ]
+}
+
+pub fn store() -> ConfigStore {
+    ConfigStore {
+        backend: store_backend(),
+        history_limit: store_history_limit(),
+        embedded: store_embedded(),
+    }
+}
+
+pub fn store_backend() -> ConfigStoreBackend {
+    ConfigStoreBackend::Redis
+}
+
+pub fn store_history_limit() -> usize {
+    20
+}
+
+pub fn store_embedded() -> ConfigStoreEmbedded {
+    ConfigStoreEmbedded {
+        path: store_embedded_path(),
+    }
+}
+
+pub fn store_embedded_path() -> PathBuf {
+    PathBuf::from("./data/store/")
+}
+
+pub fn redis() -> ConfigRedis {
+    ConfigRedis {
+        database: redis_database(),
+        pool_size: redis_pool_size(),
+        max_lifetime_seconds: redis_max_lifetime_seconds(),
+        idle_timeout_seconds: redis_idle_timeout_seconds(),
+        connection_timeout_seconds: redis_connection_timeout_seconds(),
+        cache_refresh_seconds: redis_cache_refresh_seconds(),
+        cache_expire_seconds: redis_cache_expire_seconds(),
+        cache_max_entries: redis_cache_max_entries(),
+        cache_max_negative_entries: redis_cache_max_negative_entries(),
+        stale_max_seconds: redis_stale_max_seconds(),
+        stale_ttl: redis_stale_ttl(),
+        warmup_enable: redis_warmup_enable(),
+        warmup_timeout_seconds: redis_warmup_timeout_seconds(),
+        invalidate_enable: redis_invalidate_enable(),
+        invalidate_channel: redis_invalidate_channel(),
+        master: redis_master(),
+        rescue: None,
+        sentinel: None,
+        cluster: None,
+    }
+}
+
 pub fn redis_database() -> u8 {
     0
 }
@@ -159,10 +329,47 @@ pub fn redis_cache_expire_seconds() -> u64 {
     600
 }
 
+pub fn redis_cache_max_entries() -> usize {
+    100000
+}
+
+pub fn redis_cache_max_negative_entries() -> usize {
+    20000
+}
+
+pub fn redis_stale_max_seconds() -> u64 {
+    86400
+}
+
+pub fn redis_stale_ttl() -> u32 {
+    30
+}
+
+pub fn redis_warmup_enable() -> bool {
+    false
+}
+
+pub fn redis_warmup_timeout_seconds() -> u64 {
+    10
+}
+
+pub fn redis_invalidate_enable() -> bool {
+    true
+}
+
+pub fn redis_invalidate_channel() -> String {
+    "cl:invalidate".to_string()
+}
+
 pub fn redis_master() -> ConfigRedisMaster {
     ConfigRedisMaster {
+        url: None,
         host: redis_master_host(),
         port: redis_master_port(),
+        socket_path: None,
+        tls: redis_master_tls(),
+        ca_file: None,
+        username: None,
         password: None,
     }
 }
@@ -175,6 +382,18 @@ pub fn redis_master_port() -> u16 {
     6379
 }
 
+pub fn redis_master_tls() -> bool {
+    false
+}
+
+pub fn redis_rescue_host() -> String {
+    "localhost".to_string()
+}
+
 pub fn redis_rescue_port() -> u16 {
     6379
 }
+
+pub fn redis_rescue_tls() -> bool {
+    false
+}
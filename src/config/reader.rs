@@ -25,6 +25,18 @@ impl ConfigReader {
 
         debug!("read config file: {}", &APP_ARGS.config);
 
-        toml::from_str(&conf).expect("syntax error in config file")
+        let conf: Config = toml::from_str(&conf).expect("syntax error in config file");
+
+        Self::validate(&conf);
+
+        conf
+    }
+
+    fn validate(conf: &Config) {
+        // Notice: the audit log Redis stream is stored in the Redis store, thus it cannot be \
+        //   used with other store backends.
+        if conf.http.audit.redis_enable == true && conf.store.backend != ConfigStoreBackend::Redis {
+            panic!("http.audit.redis_enable requires the redis store backend");
+        }
     }
 }
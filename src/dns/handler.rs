@@ -6,12 +6,17 @@
 
 use rand::seq::SliceRandom;
 use rand::thread_rng;
+use std::cmp;
 use std::collections::HashMap;
 use std::net::IpAddr;
 use std::sync::RwLock;
-use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
 use trust_dns_proto::rr::dnssec::SupportedAlgorithms;
-use trust_dns_proto::rr::{Name, Record, RecordType as TrustRecordType};
+use trust_dns_proto::rr::rdata::null::NULL;
+use trust_dns_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use trust_dns_proto::rr::rdata::txt::TXT;
+use trust_dns_proto::rr::{DNSClass, Name, RData, Record, RecordType as TrustRecordType};
+use trust_dns_proto::serialize::binary::BinEncodable;
 use trust_dns_server::authority::{AuthLookup, Authority};
 use trust_dns_server::authority::AuthLookupIter::Records;
 use trust_dns_server::server::{Request, RequestHandler};
@@ -24,6 +29,7 @@ use super::health::{DNSHealth, DNSHealthStatus};
 use super::metrics::{MetricsValue, METRICS_STORE};
 use super::record::{RecordName, RecordType};
 use super::zone::ZoneName;
+use crate::config::config::ConfigDNSAnyResponse;
 use crate::geo::locate::Locator;
 use crate::geo::region::RegionCode;
 use crate::store::store::{StoreError, StoreRecord};
@@ -36,6 +42,32 @@ use trust_dns_server::authority::MessageResponse;
 
 pub type Authority2 = InMemoryAuthority;
 
+const ADDITIONAL_RECORD_TYPES: [RecordType; 2] = [RecordType::A, RecordType::AAAA];
+
+// Notice: TXT records are excluded from ANY candidates, as they can be large (eg. DKIM keys), \
+//   which would make ANY queries over UDP usable for amplification purposes.
+const ANY_RRSET_RECORD_TYPES: [RecordType; 5] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::CNAME,
+    RecordType::MX,
+    RecordType::PTR,
+];
+
+const ANY_HINFO_CODE: u16 = 13;
+const ANY_HINFO_CPU: &'static str = "RFC8482";
+const ANY_HINFO_OS: &'static str = "";
+
+// Notice: the advertised UDP payload size is the one recommended by the DNS Flag Day 2020, \
+//   which avoids IP fragmentation on most networks (regardless of what the client advertises).
+const EDNS_MAX_PAYLOAD: u16 = 1232;
+
+// Notice: this is the largest UDP payload that clients not using EDNS can receive.
+const PLAIN_MAX_PAYLOAD: u16 = 512;
+
+const CHAOS_NAMES_IDENTIFIER: [&'static str; 2] = ["id.server.", "hostname.bind."];
+const CHAOS_NAMES_VERSION: [&'static str; 2] = ["version.server.", "version.bind."];
+
 pub struct DNSHandler {
     authorities: HashMap<Name, RwLock<Authority2>>,
 }
@@ -115,7 +147,12 @@ impl DNSHandler {
         response.add_queries(request.queries().into_iter().cloned());
 
         for query in request.queries() {
-            if let Some(ref_authority) = self.find_auth_recurse(query.name()) {
+            if query.query_class() == DNSClass::CH {
+                debug!("got chaos query, serving server identity for: {}", query);
+
+                // Answer server identity queries (in the CHAOS class)
+                Self::serve_response_chaos(request, &mut response, query);
+            } else if let Some(ref_authority) = self.find_auth_recurse(query.name()) {
                 let authority = &ref_authority.read().unwrap();
                 let zone_name = ZoneName::from_trust(&authority.origin());
 
@@ -131,7 +168,21 @@ impl DNSHandler {
                 let mut rt = tokio::runtime::Runtime::new().unwrap();
                 let records_local = rt.block_on(async { authority.search(query, false, supported_algorithms) } );
 
-                if !records_local.is_empty() {
+                if query.query_type() == TrustRecordType::ANY {
+                    debug!("got any query, serving minimal response for: {}", query);
+
+                    // Answer ANY queries with a minimal response (as per RFC 8482)
+                    Self::serve_response_any(
+                        request,
+                        &mut response,
+                        &zone_name,
+                        source,
+                        query,
+                        &records_local,
+                        &authority,
+                        supported_algorithms,
+                    );
+                } else if !records_local.is_empty() {
                     debug!("found records for query from local store: {}", query);
 
                     let records_local_vec = records_local
@@ -223,6 +274,26 @@ impl DNSHandler {
             }
         }
 
+        // Attach EDNS to response? (if client made use of EDNS)
+        if let Some(request_edns) = request.edns() {
+            Self::stamp_response_edns(request_edns, &mut response);
+        }
+
+        // Attach additional records? (addresses of in-zone targets)
+        // Notice: this is done last, so that additional records are only attached as long as \
+        //   the whole response fits in the payload size that the client can receive.
+        if APP_CONF.dns.minimal_responses == false {
+            let payload_limit = match request.edns() {
+                Some(request_edns) => cmp::max(
+                    cmp::min(request_edns.max_payload(), EDNS_MAX_PAYLOAD),
+                    PLAIN_MAX_PAYLOAD,
+                ),
+                None => PLAIN_MAX_PAYLOAD,
+            };
+
+            self.serve_additional_records(source, payload_limit, &mut response);
+        }
+
         response
     }
 
@@ -329,57 +400,45 @@ impl DNSHandler {
                 let mut records = Vec::new();
 
                 if let &Some(ref record_type_inner) = record_type {
-                    match APP_STORE.get(&zone_name, &record_name, record_type_inner) {
-                        Ok(record) => {
+                    if let Some(record) =
+                        Self::record_from_store(&zone_name, &record_name, record_type_inner)?
+                    {
+                        debug!(
+                            "found record in store for query: {} {}; got: {:?}",
+                            query_name_effective, query_type, record
+                        );
+
+                        // Append record direct results
+                        Self::parse_from_records(
+                            query_name_client,
+                            record_type_inner,
+                            source,
+                            &zone_name,
+                            &record,
+                            &mut records,
+                        );
+                    }
+
+                    // Look for a CNAME result?
+                    if record_type_inner != &RecordType::CNAME {
+                        if let Some(record_cname) =
+                            Self::record_from_store(&zone_name, &record_name, &RecordType::CNAME)?
+                        {
                             debug!(
-                                "found record in store for query: {} {}; got: {:?}",
-                                query_name_effective, query_type, record
+                                "found cname hint record in store for query: {} {}; got: {:?}",
+                                query_name_effective, query_type, record_cname
                             );
 
-                            // Append record direct results
+                            // Append CNAME hint results
                             Self::parse_from_records(
                                 query_name_client,
                                 record_type_inner,
                                 source,
                                 &zone_name,
-                                &record,
+                                &record_cname,
                                 &mut records,
                             );
                         }
-                        Err(StoreError::Disconnected) => {
-                            // Store is down, consider it as a DNS server failure (this avoids \
-                            //   polluting recursive DNS caches)
-                            return Err(ResponseCode::ServFail);
-                        }
-                        _ => {}
-                    }
-
-                    // Look for a CNAME result?
-                    if record_type_inner != &RecordType::CNAME {
-                        match APP_STORE.get(&zone_name, &record_name, &RecordType::CNAME) {
-                            Ok(record_cname) => {
-                                debug!(
-                                    "found cname hint record in store for query: {} {}; got: {:?}",
-                                    query_name_effective, query_type, record_cname
-                                );
-
-                                // Append CNAME hint results
-                                Self::parse_from_records(
-                                    query_name_client,
-                                    record_type_inner,
-                                    source,
-                                    &zone_name,
-                                    &record_cname,
-                                    &mut records,
-                                );
-                            }
-                            Err(StoreError::Disconnected) => {
-                                // Store is down, consider it as a DNS server failure (this avoids \
-                                //   polluting recursive DNS caches)
-                                return Err(ResponseCode::ServFail);
-                            }
-                            _ => {}
-                        }
                     }
                 }
 
@@ -401,6 +460,46 @@ impl DNSHandler {
         Ok(None)
     }
 
+    fn record_from_store(
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<Option<StoreRecord>, ResponseCode> {
+        match APP_STORE.get(zone_name, record_name, record_type) {
+            Ok(record) => Ok(Some(record)),
+            Err(StoreError::Disconnected) => {
+                // Store is down, attempt to serve a stale record from the local cache (as per \
+                //   RFC 8767)
+                match APP_STORE.get_stale(zone_name, record_name, record_type) {
+                    Ok(mut record) => {
+                        info!(
+                            "store is down, serving stale record for: {:?} on type: {:?}",
+                            record_name, record_type
+                        );
+
+                        METRICS_STORE.stack(
+                            zone_name,
+                            MetricsValue::AnswerStale(&Some(record_type.to_owned())),
+                        );
+
+                        // Serve stale record with a short TTL, so that resolvers come back \
+                        //   shortly (hopefully once the store is up again)
+                        record.ttl = Some(APP_CONF.redis.stale_ttl);
+
+                        Ok(Some(record))
+                    }
+                    Err(StoreError::NotFound) => Ok(None),
+                    Err(_) => {
+                        // Store is down and record is not known, consider it as a DNS server \
+                        //   failure (this avoids polluting recursive DNS caches)
+                        Err(ResponseCode::ServFail)
+                    }
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
     fn parse_from_records(
         query_name_client: &Name,
         record_type: &RecordType,
@@ -545,7 +644,9 @@ impl DNSHandler {
                     if let Ok(value_data) = value.to_trust(&record.kind) {
                         records.push(Record::from_rdata(
                             query_name_client.to_owned(),
-                            record.ttl.unwrap_or(APP_CONF.dns.record_ttl),
+                            record
+                                .ttl
+                                .unwrap_or(APP_CONF.dns.zone_record_ttl(zone_name.to_str())),
                             type_data,
                             value_data,
                         ));
@@ -600,6 +701,338 @@ impl DNSHandler {
         }
     }
 
+    fn serve_response_any(
+        request: &Message,
+        response: &mut Message,
+        zone_name: &Option<ZoneName>,
+        source: IpAddr,
+        query: &Query,
+        records_local: &AuthLookup,
+        authority: &Authority2,
+        supported_algorithms: SupportedAlgorithms,
+    ) {
+        // Stack query type to metrics? (ANY is not a stored record type)
+        if let Some(ref zone_name) = zone_name {
+            METRICS_STORE.stack(zone_name, MetricsValue::QueryType(&None));
+        }
+
+        let record_name = RecordName::from_trust(&authority.origin(), query.name());
+
+        match (zone_name.as_ref(), record_name) {
+            (Some(zone_name_inner), Some(record_name)) => {
+                // Pick one representative RRset? (first record type found for name)
+                let mut records = if APP_CONF.dns.any_response == ConfigDNSAnyResponse::RRSet {
+                    match Self::pick_any_rrset(query.name(), source, zone_name_inner, &record_name)
+                    {
+                        Ok(records) => records,
+                        Err(code) => {
+                            Self::stamp_response(
+                                request,
+                                response,
+                                authority,
+                                supported_algorithms,
+                                code,
+                                zone_name,
+                                false,
+                            );
+
+                            return;
+                        }
+                    }
+                } else {
+                    Vec::new()
+                };
+
+                // No RRset picked? Synthesize an HINFO record, if name exists
+                if records.is_empty() {
+                    let name_exists = match records_local {
+                        AuthLookup::NoName => {
+                            match Self::check_name_exists(zone_name_inner, &record_name) {
+                                Ok(exists) => exists,
+                                Err(code) => {
+                                    Self::stamp_response(
+                                        request,
+                                        response,
+                                        authority,
+                                        supported_algorithms,
+                                        code,
+                                        zone_name,
+                                        false,
+                                    );
+
+                                    return;
+                                }
+                            }
+                        }
+                        _ => true,
+                    };
+
+                    if name_exists == true {
+                        records.push(Self::make_any_hinfo(
+                            query.name(),
+                            APP_CONF.dns.zone_record_ttl(zone_name_inner.to_str()),
+                        ));
+                    } else {
+                        Self::stamp_response(
+                            request,
+                            response,
+                            authority,
+                            supported_algorithms,
+                            ResponseCode::NXDomain,
+                            zone_name,
+                            false,
+                        );
+
+                        return;
+                    }
+                }
+
+                Self::serve_response_records(
+                    request,
+                    response,
+                    zone_name,
+                    records,
+                    authority,
+                    supported_algorithms,
+                );
+            }
+            _ => {
+                Self::stamp_response(
+                    request,
+                    response,
+                    authority,
+                    supported_algorithms,
+                    ResponseCode::NXDomain,
+                    zone_name,
+                    false,
+                );
+            }
+        }
+    }
+
+    pub fn pick_any_rrset(
+        query_name: &Name,
+        source: IpAddr,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+    ) -> Result<Vec<Record>, ResponseCode> {
+        let mut records = Vec::new();
+
+        for record_type in ANY_RRSET_RECORD_TYPES.iter() {
+            if let Some(record) = Self::record_from_store(zone_name, record_name, record_type)? {
+                Self::parse_from_records(
+                    query_name,
+                    record_type,
+                    source,
+                    zone_name,
+                    &record,
+                    &mut records,
+                );
+
+                if !records.is_empty() {
+                    debug!(
+                        "picked representative {:?} rrset for any query: {}",
+                        record_type, query_name
+                    );
+
+                    break;
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    pub fn make_any_hinfo(query_name: &Name, ttl: u32) -> Record {
+        // Notice: HINFO is not natively supported by the DNS library, thus its data is encoded \
+        //   manually as two character-strings (CPU and OS).
+        let mut hinfo_data = Vec::new();
+
+        for field in &[ANY_HINFO_CPU, ANY_HINFO_OS] {
+            hinfo_data.push(field.len() as u8);
+            hinfo_data.extend_from_slice(field.as_bytes());
+        }
+
+        Record::from_rdata(
+            query_name.to_owned(),
+            ttl,
+            TrustRecordType::Unknown(ANY_HINFO_CODE),
+            RData::Unknown {
+                code: ANY_HINFO_CODE,
+                rdata: NULL::with(hinfo_data),
+            },
+        )
+    }
+
+    fn serve_response_chaos(request: &Message, response: &mut Message, query: &Query) {
+        let query_name = query.name().to_string().to_lowercase();
+
+        // Acquire identity value for requested name (if any)
+        let value = if APP_CONF.dns.chaos_enable == false {
+            None
+        } else if CHAOS_NAMES_IDENTIFIER.contains(&query_name.as_str()) {
+            Some(&APP_CONF.server.identifier)
+        } else if CHAOS_NAMES_VERSION.contains(&query_name.as_str())
+            && APP_CONF.server.version.is_empty() == false
+        {
+            Some(&APP_CONF.server.version)
+        } else {
+            None
+        };
+
+        // Stamp response with 'RD' flag? (if requested by client)
+        if request.recursion_desired() == true {
+            response.set_recursion_desired(true);
+        }
+
+        if let Some(value) = value {
+            response.set_response_code(ResponseCode::NoError);
+            response.set_authoritative(true);
+
+            // Push value as a TXT record? (only if TXT or ANY was requested)
+            match query.query_type() {
+                TrustRecordType::TXT | TrustRecordType::ANY => {
+                    let mut record = Record::from_rdata(
+                        query.name().to_owned(),
+                        0,
+                        TrustRecordType::TXT,
+                        RData::TXT(TXT::new(vec![value.to_owned()])),
+                    );
+
+                    record.set_dns_class(DNSClass::CH);
+
+                    response.add_answer(record);
+                }
+                _ => {}
+            }
+        } else {
+            debug!("refused chaos query for: {}", query);
+
+            response.set_response_code(ResponseCode::Refused);
+        }
+    }
+
+    fn stamp_response_edns(request_edns: &Edns, response: &mut Message) {
+        let mut response_edns = Edns::new();
+
+        response_edns.set_max_payload(EDNS_MAX_PAYLOAD);
+        response_edns.set_dnssec_ok(request_edns.dnssec_ok());
+
+        // Append NSID option? (if requested by client, as per RFC 5001)
+        if APP_CONF.dns.nsid_enable == true && request_edns.option(EdnsCode::NSID).is_some() {
+            debug!("client requested nsid, appending server identifier");
+
+            response_edns.set_option(EdnsOption::from((
+                EdnsCode::NSID,
+                APP_CONF.server.identifier.as_bytes(),
+            )));
+        }
+
+        response.set_edns(response_edns);
+    }
+
+    fn serve_additional_records(
+        &self,
+        source: IpAddr,
+        payload_limit: u16,
+        response: &mut Message,
+    ) {
+        let mut targets: Vec<Name> = Vec::new();
+
+        // Collect unique targets from answers (ie. MX exchanges, NS hosts and SRV targets)
+        for record in response.answers() {
+            let target = match record.rdata() {
+                RData::MX(ref mx) => Some(mx.exchange()),
+                RData::NS(ref ns) => Some(ns),
+                RData::SRV(ref srv) => Some(srv.target()),
+                _ => None,
+            };
+
+            if let Some(target) = target {
+                if targets.contains(target) == false {
+                    targets.push(target.to_owned());
+                }
+            }
+        }
+
+        let mut records = Vec::new();
+
+        for target in targets {
+            // Only attach records for targets that are part of a served zone
+            if let Some(ref_authority) = self.find_auth_recurse(&target) {
+                let authority = &ref_authority.read().unwrap();
+
+                if let (Some(zone_name), Some(record_name)) = (
+                    ZoneName::from_trust(&authority.origin()),
+                    RecordName::from_trust(&authority.origin(), &target),
+                ) {
+                    for record_type in ADDITIONAL_RECORD_TYPES.iter() {
+                        // Notice: store errors are ignored there, as additional records are \
+                        //   optional and should never fail the whole response.
+                        if let Ok(Some(record)) =
+                            Self::record_from_store(&zone_name, &record_name, record_type)
+                        {
+                            debug!(
+                                "found additional record in store for target: {} {:?}",
+                                target, record_type
+                            );
+
+                            Self::parse_from_records(
+                                &target,
+                                record_type,
+                                source,
+                                &zone_name,
+                                &record,
+                                &mut records,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if !records.is_empty() {
+            // Notice: additional records are optional, thus they are dropped rather than \
+            //   having the response marked as truncated. Encoded record sizes do not account for \
+            //   name compression, which makes the computed payload size an upper bound.
+            let mut payload_size = match response.to_vec() {
+                Ok(encoded) => encoded.len(),
+                Err(err) => {
+                    warn!(
+                        "could not encode response to attach additional records: {}",
+                        err
+                    );
+
+                    return;
+                }
+            };
+
+            let records_count = records.len();
+            let mut attached_count = 0;
+
+            for record in records {
+                let record_size = match record.to_bytes() {
+                    Ok(encoded) => encoded.len(),
+                    Err(_) => break,
+                };
+
+                if payload_size + record_size > payload_limit as usize {
+                    break;
+                }
+
+                payload_size += record_size;
+                attached_count += 1;
+
+                response.add_additional(record);
+            }
+
+            debug!(
+                "attached {} out of {} additional records to response",
+                attached_count, records_count
+            );
+        }
+    }
+
     fn stamp_response(
         request: &Message,
         response: &mut Message,
@@ -634,7 +1067,21 @@ impl DNSHandler {
             if soa_records.is_empty() {
                 warn!("no soa record for: {:?}", authority.origin());
             } else {
-                response.add_name_servers(soa_records.iter().cloned());
+                // Resolvers cache negative answers for min(SOA TTL, SOA MINIMUM) (as per RFC \
+                //   2308), thus the SOA TTL is lowered to the negative TTL, which is also the \
+                //   SOA MINIMUM value.
+                let negative_ttl = match zone_name {
+                    Some(zone_name) => APP_CONF.dns.zone_negative_ttl(zone_name.to_str()),
+                    None => APP_CONF.dns.negative_ttl,
+                };
+
+                response.add_name_servers(soa_records.iter().map(|soa_record| {
+                    let mut soa_record = soa_record.to_owned();
+
+                    soa_record.set_ttl(negative_ttl);
+
+                    soa_record
+                }));
             }
         }
     }
@@ -643,6 +1090,8 @@ impl DNSHandler {
         zone_name: &ZoneName,
         record_name: &RecordName,
     ) -> Result<bool, ResponseCode> {
+        let mut is_disconnected = false;
+
         // Exhaust all record types
         for record_type in RecordType::list_choices() {
             // A record exists for name and type?
@@ -652,14 +1101,24 @@ impl DNSHandler {
                     return Ok(true);
                 }
                 Err(StoreError::Disconnected) => {
-                    // Store is down, consider it as a DNS server failure (this avoids polluting \
-                    //   recursive DNS caches); abort there.
-                    return Err(ResponseCode::ServFail);
+                    // Store is down, check if a stale record exists for name and type (as per \
+                    //   RFC 8767)
+                    match APP_STORE.get_stale(zone_name, record_name, &record_type) {
+                        Ok(_) => return Ok(true),
+                        Err(StoreError::NotFound) => {}
+                        Err(_) => is_disconnected = true,
+                    }
                 }
                 _ => {}
             }
         }
 
+        // Store is down, and name could not be found in stale records? Consider it as a DNS \
+        //   server failure (this avoids polluting recursive DNS caches)
+        if is_disconnected == true {
+            return Err(ResponseCode::ServFail);
+        }
+
         // No alternate record found, consider name as non-existing.
         Ok(false)
     }
@@ -135,6 +135,11 @@ impl DNSHealth {
         HEALTH_DEAD_REGISTER.read().unwrap().contains(chain)
     }
 
+    #[cfg(test)]
+    pub fn register_dead(chain: (ZoneName, RecordName, RecordValue)) {
+        HEALTH_DEAD_REGISTER.write().unwrap().insert(chain);
+    }
+
     fn should_check_record(record_type: &RecordType) -> bool {
         match record_type {
             RecordType::A | RecordType::AAAA | RecordType::CNAME => true,
@@ -17,15 +17,7 @@ use super::handler::Authority2;
 use super::handler::DNSHandler;
 use crate::APP_CONF;
 
-lazy_static! {
-    static ref NAME_SOA_MASTER: Name =
-        Name::parse(&APP_CONF.dns.soa_master, Some(&Name::new())).expect("invalid soa master");
-    static ref NAME_SOA_RESPONSIBLE: Name =
-        Name::parse(&APP_CONF.dns.soa_responsible, Some(&Name::new()))
-            .expect("invalid soa responsible");
-}
-
-static SERIAL_DEFAULT: u32 = 1;
+pub static SERIAL_DEFAULT: u32 = 1;
 
 pub struct DNSListenBuilder;
 pub struct DNSListen;
@@ -73,23 +65,35 @@ impl DNSListen {
         }
     }
 
-    fn map_authority(zone_name: &str) -> Result<(Name, Authority2), ()> {
+    pub fn map_authority(zone_name: &str) -> Result<(Name, Authority2), ()> {
         if let Ok(name) = Name::parse(zone_name, Some(&Name::new())) {
             let mut records = BTreeMap::new();
 
+            // Acquire zone settings (fallback on global settings if not overridden)
+            let record_ttl = APP_CONF.dns.zone_record_ttl(zone_name);
+
+            let name_soa_master =
+                Name::parse(APP_CONF.dns.zone_soa_master(zone_name), Some(&Name::new()))
+                    .expect("invalid soa master");
+            let name_soa_responsible = Name::parse(
+                APP_CONF.dns.zone_soa_responsible(zone_name),
+                Some(&Name::new()),
+            )
+            .expect("invalid soa responsible");
+
             // Insert base SOA records
             let soa_records = RecordSet::from(Record::from_rdata(
                 name.to_owned(),
-                APP_CONF.dns.record_ttl,
+                APP_CONF.dns.zone_soa_ttl(zone_name),
                 RecordType::SOA,
                 RData::SOA(SOA::new(
-                    NAME_SOA_MASTER.to_owned(),
-                    NAME_SOA_RESPONSIBLE.to_owned(),
+                    name_soa_master,
+                    name_soa_responsible,
                     SERIAL_DEFAULT,
-                    APP_CONF.dns.soa_refresh,
-                    APP_CONF.dns.soa_retry,
-                    APP_CONF.dns.soa_expire,
-                    APP_CONF.dns.soa_ttl,
+                    APP_CONF.dns.zone_soa_refresh(zone_name),
+                    APP_CONF.dns.zone_soa_retry(zone_name),
+                    APP_CONF.dns.zone_soa_expire(zone_name),
+                    APP_CONF.dns.zone_negative_ttl(zone_name),
                 )),
             ));
 
@@ -98,11 +102,11 @@ impl DNSListen {
             // Insert base NS records
             let mut ns_records = RecordSet::new(&name, RecordType::NS, SERIAL_DEFAULT);
 
-            for nameserver in &APP_CONF.dns.nameservers {
+            for nameserver in APP_CONF.dns.zone_nameservers(zone_name) {
                 ns_records.insert(
                     Record::from_rdata(
                         name.to_owned(),
-                        APP_CONF.dns.record_ttl,
+                        record_ttl,
                         RecordType::NS,
                         RData::NS(
                             Name::parse(nameserver, Some(&Name::new()))
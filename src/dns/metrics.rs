@@ -33,6 +33,7 @@ pub struct DNSMetricsTick;
 pub type MetricsStoreQueryTypeType = HashMap<Option<RecordType>, MetricsStoreCountType>;
 pub type MetricsStoreQueryOriginType = HashMap<Option<CountryCode>, MetricsStoreCountType>;
 pub type MetricsStoreAnswerCodeType = HashMap<Option<CodeName>, MetricsStoreCountType>;
+pub type MetricsStoreAnswerStaleType = HashMap<Option<RecordType>, MetricsStoreCountType>;
 
 struct MetricsStoreBuilder;
 struct MetricsStoreZoneBuilder;
@@ -46,18 +47,21 @@ pub struct MetricsStoreZone {
     pub query_type: [MetricsStoreQueryTypeType; (METRICS_BACKLOG_MINUTES + 1)],
     pub query_origin: [MetricsStoreQueryOriginType; (METRICS_BACKLOG_MINUTES + 1)],
     pub answer_code: [MetricsStoreAnswerCodeType; (METRICS_BACKLOG_MINUTES + 1)],
+    pub answer_stale: [MetricsStoreAnswerStaleType; (METRICS_BACKLOG_MINUTES + 1)],
 }
 
 pub enum MetricsType {
     QueryType,
     QueryOrigin,
     AnswerCode,
+    AnswerStale,
 }
 
 pub enum MetricsValue<'a> {
     QueryType(&'a Option<RecordType>),
     QueryOrigin(&'a Option<CountryCode>),
     AnswerCode(&'a Option<CodeName>),
+    AnswerStale(&'a Option<RecordType>),
 }
 
 impl MetricsStoreBuilder {
@@ -107,15 +111,17 @@ impl DNSMetricsTick {
         let mut zones_write = METRICS_STORE.zones.write().unwrap();
 
         for (_, zone_store) in zones_write.iter_mut() {
-            let (store_query_type, store_query_origin, store_answer_code) = (
+            let (store_query_type, store_query_origin, store_answer_code, store_answer_stale) = (
                 &mut zone_store.query_type,
                 &mut zone_store.query_origin,
                 &mut zone_store.answer_code,
+                &mut zone_store.answer_stale,
             );
 
             gen_metrics_tick_perform_item!(store_query_type, METRICS_BACKLOG_MINUTES);
             gen_metrics_tick_perform_item!(store_query_origin, METRICS_BACKLOG_MINUTES);
             gen_metrics_tick_perform_item!(store_answer_code, METRICS_BACKLOG_MINUTES);
+            gen_metrics_tick_perform_item!(store_answer_stale, METRICS_BACKLOG_MINUTES);
         }
     }
 }
@@ -139,6 +145,9 @@ impl MetricsStore {
                     self.stack_query_origin(zone_store, origin_country)
                 }
                 MetricsValue::AnswerCode(code) => self.stack_answer_code(zone_store, code),
+                MetricsValue::AnswerStale(record_type) => {
+                    self.stack_answer_stale(zone_store, record_type)
+                }
             }
         }
     }
@@ -167,6 +176,9 @@ impl MetricsStore {
                     MetricsType::AnswerCode => {
                         aggregated_map = self.aggregate_answer_code(zone_store, aggregate_limit);
                     }
+                    MetricsType::AnswerStale => {
+                        aggregated_map = self.aggregate_answer_stale(zone_store, aggregate_limit);
+                    }
                 }
             } else {
                 aggregated_map = HashMap::new();
@@ -212,6 +224,17 @@ impl MetricsStore {
         gen_metrics_stack_item!(answer_code_counters, code);
     }
 
+    fn stack_answer_stale(&self, store: &mut MetricsStoreZone, record_type: &Option<RecordType>) {
+        debug!(
+            "stacking answer stale metric for record type: {:?}",
+            record_type
+        );
+
+        let answer_stale_counters = &mut store.answer_stale[0];
+
+        gen_metrics_stack_item!(answer_stale_counters, record_type);
+    }
+
     fn aggregate_query_type(
         &self,
         store: &MetricsStoreZone,
@@ -241,6 +264,16 @@ impl MetricsStore {
 
         gen_metrics_aggregate_item!(store_target, aggregate_limit, METRICS_BACKLOG_MINUTES)
     }
+
+    fn aggregate_answer_stale(
+        &self,
+        store: &MetricsStoreZone,
+        aggregate_limit: u8,
+    ) -> HashMap<String, MetricsStoreCountType> {
+        let store_target = &store.answer_stale;
+
+        gen_metrics_aggregate_item!(store_target, aggregate_limit, METRICS_BACKLOG_MINUTES)
+    }
 }
 
 impl MetricsTimespan {
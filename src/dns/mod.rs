@@ -10,8 +10,12 @@ mod macros;
 mod code;
 mod handler;
 
+#[cfg(test)]
+mod tests;
+
 pub mod health;
 pub mod listen;
 pub mod metrics;
 pub mod record;
 pub mod zone;
+pub mod zonefile;
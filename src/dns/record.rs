@@ -182,6 +182,32 @@ impl RecordName {
 }
 
 impl RecordValue {
+    pub fn from_trust(rdata: &TrustRData) -> Option<RecordValue> {
+        match rdata {
+            TrustRData::A(value) => Some(RecordValue(value.to_string())),
+            TrustRData::AAAA(value) => Some(RecordValue(value.to_string())),
+            TrustRData::CNAME(value) | TrustRData::PTR(value) => {
+                Some(RecordValue(Self::from_trust_name(value)))
+            }
+            TrustRData::MX(value) => Some(RecordValue(format!(
+                "{} {}",
+                value.preference(),
+                Self::from_trust_name(value.exchange())
+            ))),
+            TrustRData::TXT(value) => {
+                // Join TXT parts back (they are split again when served)
+                Some(RecordValue(
+                    value
+                        .txt_data()
+                        .iter()
+                        .map(|chunk| String::from_utf8_lossy(chunk))
+                        .collect::<String>(),
+                ))
+            }
+            _ => None,
+        }
+    }
+
     pub fn to_trust(&self, record_type: &RecordType) -> Result<TrustRData, ()> {
         match record_type {
             RecordType::A => {
@@ -245,6 +271,17 @@ impl RecordValue {
     pub fn to_str(&self) -> &str {
         &self.0
     }
+
+    fn from_trust_name(name: &TrustName) -> String {
+        // Names are stored without their trailing root label dot
+        name.to_string().trim_end_matches('.').to_owned()
+    }
+}
+
+impl RecordValues {
+    pub fn from_list(values: Vec<RecordValue>) -> RecordValues {
+        RecordValues(values)
+    }
 }
 
 impl RecordBlackhole {
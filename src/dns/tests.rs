@@ -0,0 +1,475 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use serde_json;
+use std::str::FromStr;
+use trust_dns_proto::op::{Edns, Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use trust_dns_proto::rr::{DNSClass, Name, RData, Record, RecordType as TrustRecordType};
+
+use super::handler::DNSHandler;
+use super::health::DNSHealth;
+use super::listen::DNSListen;
+use super::record::{RecordName, RecordValue};
+use super::zone::ZoneName;
+use crate::store::store::StoreRecord;
+use crate::APP_CONF;
+use crate::APP_STORE;
+
+// Notice: test sources are located using the test geo database, which maps '1.1.1.0/24' to \
+//   France (region: WEU), '2.2.2.0/24' to the United States (region: SNAM), and nothing else.
+const TEST_ZONE: &'static str = "example.com";
+const TEST_SOURCE_FR: &'static str = "1.1.1.1";
+const TEST_SOURCE_US: &'static str = "2.2.2.2";
+const TEST_SOURCE_UNKNOWN: &'static str = "3.3.3.3";
+
+fn make_handler() -> DNSHandler {
+    let mut handler = DNSHandler::new();
+
+    for zone_name in APP_CONF.dns.zone.keys() {
+        let (name, authority) =
+            DNSListen::map_authority(zone_name).expect("could not map test zone authority");
+
+        handler.upsert(name, authority);
+    }
+
+    handler
+}
+
+fn store_record(record_json: &str) {
+    let zone_name = ZoneName::from_str(TEST_ZONE).expect("invalid test zone");
+    let record = serde_json::from_str::<StoreRecord>(record_json).expect("invalid test record");
+
+    assert!(APP_STORE.set(&zone_name, record).is_ok());
+}
+
+fn make_query(query_name: &str, query_type: TrustRecordType) -> Query {
+    Query::query(
+        Name::from_str(query_name).expect("invalid test query name"),
+        query_type,
+    )
+}
+
+fn make_request(query: Query) -> Message {
+    let mut request = Message::new();
+
+    request
+        .set_id(1)
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query);
+
+    request.add_query(query);
+
+    request
+}
+
+fn lookup_request(source: &str, request: &Message) -> Message {
+    make_handler().lookup(source.parse().expect("invalid test source"), request)
+}
+
+fn lookup(source: &str, query_name: &str, query_type: TrustRecordType) -> Message {
+    lookup_request(source, &make_request(make_query(query_name, query_type)))
+}
+
+fn answer_values(response: &Message) -> Vec<String> {
+    let mut values = response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.rdata() {
+            RData::A(address) => Some(address.to_string()),
+            RData::AAAA(address) => Some(address.to_string()),
+            RData::CNAME(name) => Some(name.to_string().trim_end_matches(".").to_owned()),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+
+    values.sort();
+
+    values
+}
+
+#[test]
+fn it_serves_global_values() {
+    store_record(r#"{"kind":"a","name":"global.@","values":["10.0.0.1","10.0.0.2"]}"#);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "global.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(answer_values(&response), vec!["10.0.0.1", "10.0.0.2"]);
+}
+
+#[test]
+fn it_serves_geo_values() {
+    store_record(
+        r#"{
+            "kind": "a",
+            "name": "geo.@",
+            "regions": {"weu": ["10.1.0.1"], "snam": ["10.1.0.2"]},
+            "values": ["10.1.0.9"]
+        }"#,
+    );
+
+    let response_fr = lookup(TEST_SOURCE_FR, "geo.example.com.", TrustRecordType::A);
+    let response_us = lookup(TEST_SOURCE_US, "geo.example.com.", TrustRecordType::A);
+    let response_unknown = lookup(TEST_SOURCE_UNKNOWN, "geo.example.com.", TrustRecordType::A);
+
+    assert_eq!(answer_values(&response_fr), vec!["10.1.0.1"]);
+    assert_eq!(answer_values(&response_us), vec!["10.1.0.2"]);
+    assert_eq!(answer_values(&response_unknown), vec!["10.1.0.9"]);
+}
+
+#[test]
+fn it_serves_wildcard_values() {
+    store_record(r#"{"kind":"a","name":"*.wildcard.@","values":["10.2.0.1"]}"#);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "any.wildcard.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(answer_values(&response), vec!["10.2.0.1"]);
+
+    // Answer must be owned by the queried name, not by the wildcard name
+    assert_eq!(
+        response.answers()[0].name(),
+        &Name::from_str("any.wildcard.example.com.").unwrap()
+    );
+}
+
+#[test]
+fn it_serves_cname_for_other_types() {
+    store_record(r#"{"kind":"cname","name":"alias.@","values":["target.example.net"]}"#);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "alias.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(answer_values(&response), vec!["target.example.net"]);
+    assert_eq!(response.answers()[0].rr_type(), TrustRecordType::CNAME);
+}
+
+#[test]
+fn it_blackholes_countries() {
+    store_record(r#"{"kind":"a","name":"blackhole.@","blackhole":["FR"],"values":["10.3.0.1"]}"#);
+
+    let response_fr = lookup(TEST_SOURCE_FR, "blackhole.example.com.", TrustRecordType::A);
+    let response_us = lookup(TEST_SOURCE_US, "blackhole.example.com.", TrustRecordType::A);
+
+    assert_eq!(response_fr.response_code(), ResponseCode::NoError);
+    assert!(answer_values(&response_fr).is_empty());
+    assert_eq!(answer_values(&response_us), vec!["10.3.0.1"]);
+}
+
+#[test]
+fn it_serves_rescue_values_when_all_dead() {
+    store_record(r#"{"kind":"a","name":"rescue.@","rescue":["10.4.0.99"],"values":["10.4.0.1"]}"#);
+
+    let response_healthy = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "rescue.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(answer_values(&response_healthy), vec!["10.4.0.1"]);
+
+    DNSHealth::register_dead((
+        ZoneName::from_str(TEST_ZONE).unwrap(),
+        RecordName::from_str("rescue.@").unwrap(),
+        serde_json::from_str::<RecordValue>(r#""10.4.0.1""#).unwrap(),
+    ));
+
+    let response_dead = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "rescue.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(answer_values(&response_dead), vec!["10.4.0.99"]);
+}
+
+#[test]
+fn it_answers_nxdomain_for_missing_names() {
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "missing.example.com.",
+        TrustRecordType::A,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(response.answers().is_empty());
+
+    // Negative answers must carry the zone SOA (as per RFC 2308)
+    assert_eq!(response.name_servers().len(), 1);
+    assert_eq!(response.name_servers()[0].rr_type(), TrustRecordType::SOA);
+    assert_eq!(response.name_servers()[0].ttl(), APP_CONF.dns.negative_ttl);
+}
+
+#[test]
+fn it_answers_nodata_for_missing_types() {
+    store_record(r#"{"kind":"a","name":"nodata.@","values":["10.5.0.1"]}"#);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "nodata.example.com.",
+        TrustRecordType::AAAA,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().is_empty());
+    assert_eq!(response.name_servers().len(), 1);
+    assert_eq!(response.name_servers()[0].rr_type(), TrustRecordType::SOA);
+}
+
+#[test]
+fn it_refuses_names_outside_of_zones() {
+    let response = lookup(TEST_SOURCE_UNKNOWN, "www.example.org.", TrustRecordType::A);
+
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert!(response.answers().is_empty());
+}
+
+#[test]
+fn it_answers_chaos_identity_queries() {
+    let mut query = make_query("id.server.", TrustRecordType::TXT);
+
+    query.set_query_class(DNSClass::CH);
+
+    let response = lookup_request(TEST_SOURCE_UNKNOWN, &make_request(query));
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.authoritative());
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(response.answers()[0].dns_class(), DNSClass::CH);
+
+    match response.answers()[0].rdata() {
+        RData::TXT(txt) => assert_eq!(
+            txt.txt_data()
+                .iter()
+                .map(|chunk| String::from_utf8_lossy(chunk))
+                .collect::<String>(),
+            APP_CONF.server.identifier
+        ),
+        _ => panic!("chaos identity answer is not a txt record"),
+    }
+}
+
+#[test]
+fn it_refuses_unknown_chaos_queries() {
+    let mut query = make_query("unknown.server.", TrustRecordType::TXT);
+
+    query.set_query_class(DNSClass::CH);
+
+    let response = lookup_request(TEST_SOURCE_UNKNOWN, &make_request(query));
+
+    assert_eq!(response.response_code(), ResponseCode::Refused);
+    assert!(response.answers().is_empty());
+}
+
+#[test]
+fn it_appends_nsid_when_requested() {
+    let mut request_edns = Edns::new();
+
+    request_edns.set_max_payload(4096);
+    request_edns.set_option(EdnsOption::from((EdnsCode::NSID, &[][..])));
+
+    let mut request = make_request(make_query("www.example.org.", TrustRecordType::A));
+
+    request.set_edns(request_edns);
+
+    let response = lookup_request(TEST_SOURCE_UNKNOWN, &request);
+    let response_edns = response.edns().expect("response has no edns");
+
+    // Notice: the server advertises its own payload size, not the one of the client
+    assert_eq!(response_edns.max_payload(), 1232);
+    assert_eq!(
+        response_edns.option(EdnsCode::NSID),
+        Some(&EdnsOption::from((
+            EdnsCode::NSID,
+            APP_CONF.server.identifier.as_bytes()
+        )))
+    );
+}
+
+#[test]
+fn it_does_not_append_nsid_when_not_requested() {
+    let mut request = make_request(make_query("www.example.org.", TrustRecordType::A));
+
+    request.set_edns(Edns::new());
+
+    let response = lookup_request(TEST_SOURCE_UNKNOWN, &request);
+
+    assert!(response
+        .edns()
+        .expect("response has no edns")
+        .option(EdnsCode::NSID)
+        .is_none());
+}
+
+fn store_additional_targets(record_name: &str, targets_count: usize, values_count: usize) {
+    let mut exchanges = Vec::new();
+
+    for target_index in 0..targets_count {
+        let target_name = format!("{}-mx{}", record_name, target_index);
+
+        let values = (0..values_count)
+            .map(|value_index| format!("\"10.8.{}.{}\"", target_index, value_index))
+            .collect::<Vec<String>>();
+
+        store_record(&format!(
+            r#"{{"kind":"a","name":"{}.@","values":[{}]}}"#,
+            target_name,
+            values.join(",")
+        ));
+
+        exchanges.push(format!("\"10 {}.{}\"", target_name, TEST_ZONE));
+    }
+
+    store_record(&format!(
+        r#"{{"kind":"mx","name":"{}.@","values":[{}]}}"#,
+        record_name,
+        exchanges.join(",")
+    ));
+}
+
+#[test]
+fn it_caps_additional_records_to_plain_payload() {
+    store_additional_targets("additional-plain", 3, 10);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "additional-plain.example.com.",
+        TrustRecordType::MX,
+    );
+
+    assert_eq!(response.answers().len(), 3);
+    assert!(response.additionals().is_empty() == false);
+    assert!(response.additionals().len() < 30);
+    assert!(response.truncated() == false);
+    assert!(response.to_vec().expect("could not encode response").len() <= 512);
+}
+
+#[test]
+fn it_caps_additional_records_to_edns_payload() {
+    store_additional_targets("additional-edns", 6, 10);
+
+    let mut request_edns = Edns::new();
+
+    request_edns.set_max_payload(4096);
+
+    let mut request = make_request(make_query(
+        "additional-edns.example.com.",
+        TrustRecordType::MX,
+    ));
+
+    request.set_edns(request_edns);
+
+    let response = lookup_request(TEST_SOURCE_UNKNOWN, &request);
+
+    // Notice: the client payload size is capped to the one advertised by the server
+    assert!(response.additionals().len() > 12);
+    assert!(response.additionals().len() < 60);
+    assert!(response.truncated() == false);
+    assert!(response.to_vec().expect("could not encode response").len() <= 1232);
+}
+
+fn pick_any_rrset(record_name: &str, records_json: &[&str]) -> Vec<Record> {
+    for record_json in records_json {
+        store_record(record_json);
+    }
+
+    DNSHandler::pick_any_rrset(
+        &Name::from_str(&format!("{}.{}.", record_name, TEST_ZONE))
+            .expect("invalid test query name"),
+        TEST_SOURCE_UNKNOWN.parse().expect("invalid test source"),
+        &ZoneName::from_str(TEST_ZONE).expect("invalid test zone"),
+        &RecordName::from_str(&format!("{}.@", record_name)).expect("invalid test name"),
+    )
+    .expect("could not pick any rrset")
+}
+
+#[test]
+fn it_picks_any_rrset_by_type_priority() {
+    let records = pick_any_rrset(
+        "anyset",
+        &[
+            r#"{"kind":"mx","name":"anyset.@","values":["10 mx.example.org"]}"#,
+            r#"{"kind":"aaaa","name":"anyset.@","values":["2001:db8::1","2001:db8::2"]}"#,
+        ],
+    );
+
+    assert_eq!(records.len(), 2);
+    assert!(records
+        .iter()
+        .all(|record| record.rr_type() == TrustRecordType::AAAA));
+}
+
+#[test]
+fn it_skips_txt_for_any_rrset() {
+    let records = pick_any_rrset(
+        "anytxt",
+        &[r#"{"kind":"txt","name":"anytxt.@","values":["v=spf1 -all"]}"#],
+    );
+
+    assert!(records.is_empty());
+}
+
+#[test]
+fn it_makes_any_hinfo() {
+    let record = DNSHandler::make_any_hinfo(
+        &Name::from_str("any.example.com.").expect("invalid test query name"),
+        3600,
+    );
+
+    assert_eq!(record.rr_type(), TrustRecordType::Unknown(13));
+    assert_eq!(record.ttl(), 3600);
+
+    match record.rdata() {
+        RData::Unknown { code, rdata } => {
+            assert_eq!(*code, 13);
+            assert_eq!(rdata.anything(), Some(&b"\x07RFC8482\x00"[..]));
+        }
+        _ => panic!("any hinfo record has unexpected data"),
+    }
+}
+
+#[test]
+fn it_answers_any_with_hinfo() {
+    store_record(r#"{"kind":"a","name":"any.@","values":["10.6.0.1"]}"#);
+
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "any.example.com.",
+        TrustRecordType::ANY,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert_eq!(response.answers().len(), 1);
+    assert_eq!(
+        response.answers()[0].rr_type(),
+        TrustRecordType::Unknown(13)
+    );
+}
+
+#[test]
+fn it_answers_nxdomain_for_any_on_missing_names() {
+    let response = lookup(
+        TEST_SOURCE_UNKNOWN,
+        "any-missing.example.com.",
+        TrustRecordType::ANY,
+    );
+
+    assert_eq!(response.response_code(), ResponseCode::NXDomain);
+    assert!(response.answers().is_empty());
+}
@@ -0,0 +1,658 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use trust_dns_client::serialize::txt::{Lexer, Parser};
+use trust_dns_proto::rr::{Name as TrustName, RecordSet, RecordType as TrustRecordType};
+
+use super::listen::SERIAL_DEFAULT;
+use super::record::{RecordName, RecordType, RecordValue, RecordValues};
+use super::zone::ZoneName;
+use crate::store::store::StoreRecord;
+use crate::APP_CONF;
+
+pub struct ZoneFile;
+
+static TXT_CHUNK_MAXIMUM: usize = 255;
+static RECORD_CLASSES: [&'static str; 7] = ["IN", "CH", "CHAOS", "HS", "HESIOD", "NONE", "ANY"];
+
+#[derive(Serialize)]
+pub struct ZoneFileIssue {
+    line: usize,
+    name: Option<String>,
+
+    #[serde(rename = "type")]
+    _type: Option<String>,
+
+    reason: String,
+}
+
+pub struct ZoneFileParsed {
+    pub records: Vec<StoreRecord>,
+    pub unsupported: Vec<ZoneFileIssue>,
+    pub errors: Vec<ZoneFileIssue>,
+}
+
+impl ZoneFile {
+    pub fn parse(zone_name: &ZoneName, contents: &str) -> ZoneFileParsed {
+        let mut records: BTreeMap<(String, &'static str), StoreRecord> = BTreeMap::new();
+
+        let mut parsed = ZoneFileParsed {
+            records: Vec::new(),
+            unsupported: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        let zone_origin = match TrustName::parse(zone_name.to_str(), Some(&TrustName::new())) {
+            Ok(zone_origin) => zone_origin,
+            Err(err) => {
+                parsed
+                    .errors
+                    .push(ZoneFileIssue::error(0, format!("invalid zone: {}", err)));
+
+                return parsed;
+            }
+        };
+
+        let mut origin = zone_origin.to_owned();
+        let mut last_owner: Option<TrustName> = None;
+
+        let zone_ttl = APP_CONF.dns.zone_record_ttl(zone_name.to_str());
+        let mut default_ttl = zone_ttl;
+
+        // Notice: the zone file parser aborts on the first error, thus entries are parsed one by \
+        //   one, so that a single invalid line does not prevent other lines from being imported. \
+        //   Parser state (ie. origin, default TTL and last owner name) is carried over manually.
+        for (line, entry) in Self::split_entries(contents) {
+            let entry_trimmed = entry.trim();
+
+            // Directive? (ie. '$ORIGIN' or '$TTL')
+            if entry_trimmed.starts_with("$") {
+                let mut directive_parts = entry_trimmed.split_whitespace();
+
+                let directive = directive_parts.next().unwrap_or("").to_uppercase();
+                let argument = directive_parts.next().unwrap_or("");
+
+                match directive.as_str() {
+                    "$ORIGIN" => match TrustName::parse(argument, Some(&origin)) {
+                        Ok(directive_origin) => origin = directive_origin,
+                        Err(err) => parsed.errors.push(ZoneFileIssue::error(
+                            line,
+                            format!("invalid origin: {}", err),
+                        )),
+                    },
+                    "$TTL" => match Parser::parse_time(argument) {
+                        Ok(directive_ttl) => default_ttl = directive_ttl,
+                        Err(err) => parsed
+                            .errors
+                            .push(ZoneFileIssue::error(line, format!("invalid ttl: {}", err))),
+                    },
+                    _ => parsed.errors.push(ZoneFileIssue::error(
+                        line,
+                        format!("unsupported directive: {}", directive),
+                    )),
+                }
+
+                continue;
+            }
+
+            // Resolve owner name (inherited from the previous entry if the entry starts blank)
+            let (owner, entry_rest) = if entry.starts_with(char::is_whitespace) == true {
+                (last_owner.to_owned(), entry_trimmed)
+            } else {
+                let mut entry_parts = entry_trimmed.splitn(2, char::is_whitespace);
+
+                let owner_raw = entry_parts.next().unwrap_or("");
+                let entry_rest = entry_parts.next().unwrap_or("").trim();
+
+                let owner = if owner_raw == "@" {
+                    Ok(origin.to_owned())
+                } else {
+                    TrustName::parse(owner_raw, Some(&origin))
+                };
+
+                match owner {
+                    Ok(owner) => (Some(owner), entry_rest),
+                    Err(err) => {
+                        parsed.errors.push(ZoneFileIssue::error(
+                            line,
+                            format!("invalid owner name: {}", err),
+                        ));
+
+                        continue;
+                    }
+                }
+            };
+
+            let owner = match owner {
+                Some(owner) => owner,
+                None => {
+                    parsed.errors.push(ZoneFileIssue::error(
+                        line,
+                        "no owner name to inherit from".to_owned(),
+                    ));
+
+                    continue;
+                }
+            };
+
+            last_owner = Some(owner.to_owned());
+
+            // Notice: records that inherit the zone TTL are stored with no TTL, so that exported \
+            //   records with no TTL are imported back as-is.
+            let ttl_inherited =
+                Self::has_explicit_ttl(entry_rest) == false && default_ttl == zone_ttl;
+
+            // Notice: the blank 'IN' line sets the default class, which the parser requires but \
+            //   zone files commonly omit.
+            let entry_document = format!(
+                "$ORIGIN {}\n$TTL {}\n IN\n{} {}\n",
+                origin, default_ttl, owner, entry_rest
+            );
+
+            match Parser::new().parse(Lexer::new(&entry_document), Some(origin.to_owned())) {
+                Ok((_, record_sets)) => {
+                    for record_set in record_sets.values() {
+                        Self::map_record_set(
+                            line,
+                            &zone_origin,
+                            zone_ttl,
+                            ttl_inherited,
+                            record_set,
+                            &mut records,
+                            &mut parsed,
+                        );
+                    }
+                }
+                Err(err) => parsed
+                    .errors
+                    .push(ZoneFileIssue::error(line, err.to_string())),
+            }
+        }
+
+        parsed.records = records.into_iter().map(|(_, record)| record).collect();
+
+        parsed
+    }
+
+    pub fn render(zone_name: &ZoneName, records: &[StoreRecord]) -> String {
+        let zone_raw = zone_name.to_str();
+
+        let mut contents = String::new();
+
+        // Notice: writing to a 'String' cannot fail, thus write results are ignored.
+        let _ = writeln!(contents, "; Zone: {}", zone_raw);
+        let _ = writeln!(contents, "; Exported by Constellation");
+        let _ = writeln!(contents, "");
+        let _ = writeln!(contents, "$ORIGIN {}.", zone_raw);
+        let _ = writeln!(contents, "$TTL {}", APP_CONF.dns.zone_record_ttl(zone_raw));
+        let _ = writeln!(contents, "");
+
+        // Insert base SOA and NS records (from configuration)
+        let _ = writeln!(
+            contents,
+            "@ {} IN SOA {}. {}. ( {} {} {} {} {} )",
+            APP_CONF.dns.zone_soa_ttl(zone_raw),
+            APP_CONF.dns.zone_soa_master(zone_raw).trim_end_matches('.'),
+            APP_CONF
+                .dns
+                .zone_soa_responsible(zone_raw)
+                .trim_end_matches('.'),
+            SERIAL_DEFAULT,
+            APP_CONF.dns.zone_soa_refresh(zone_raw),
+            APP_CONF.dns.zone_soa_retry(zone_raw),
+            APP_CONF.dns.zone_soa_expire(zone_raw),
+            APP_CONF.dns.zone_negative_ttl(zone_raw)
+        );
+
+        for nameserver in APP_CONF.dns.zone_nameservers(zone_raw) {
+            let _ = writeln!(contents, "@ IN NS {}.", nameserver.trim_end_matches('.'));
+        }
+
+        // Insert stored records
+        for record in records {
+            let _ = writeln!(contents, "");
+
+            // Notice: zone files cannot hold Geo-DNS, blackhole and rescue data, thus they are \
+            //   written as comments (the JSON export format holds them losslessly).
+            if let Some(ref regions) = record.regions {
+                if let Ok(regions_json) = serde_json::to_string(regions) {
+                    let _ = writeln!(contents, "; regions: {}", regions_json);
+                }
+            }
+            if let Some(ref blackhole) = record.blackhole {
+                if blackhole.has_items() == true {
+                    if let Ok(blackhole_json) = serde_json::to_string(blackhole) {
+                        let _ = writeln!(contents, "; blackhole: {}", blackhole_json);
+                    }
+                }
+            }
+            if let Some(ref rescue) = record.rescue {
+                if rescue.is_empty() == false {
+                    if let Ok(rescue_json) = serde_json::to_string(rescue) {
+                        let _ = writeln!(contents, "; rescue: {}", rescue_json);
+                    }
+                }
+            }
+
+            let owner = match record.name.to_subdomain().trim_end_matches('.') {
+                "" => "@",
+                subdomain => subdomain,
+            };
+            let ttl = record
+                .ttl
+                .map(|ttl| format!(" {}", ttl))
+                .unwrap_or("".to_owned());
+
+            for value in record.values.iter() {
+                let _ = writeln!(
+                    contents,
+                    "{}{} IN {} {}",
+                    owner,
+                    ttl,
+                    record.kind.to_str().to_uppercase(),
+                    Self::render_value(&record.kind, value)
+                );
+            }
+        }
+
+        contents
+    }
+
+    fn render_value(record_type: &RecordType, value: &RecordValue) -> String {
+        match record_type {
+            RecordType::A | RecordType::AAAA => value.to_str().to_owned(),
+            RecordType::CNAME | RecordType::PTR => {
+                format!("{}.", value.to_str().trim_end_matches('.'))
+            }
+            RecordType::MX => {
+                let mut mx_parts = value.split(" ");
+
+                format!(
+                    "{} {}.",
+                    mx_parts.next().unwrap_or("0"),
+                    mx_parts.next().unwrap_or("").trim_end_matches('.')
+                )
+            }
+            RecordType::TXT => {
+                // Split TXT value into quoted character strings (of 255 bytes at most)
+                let mut chunks = vec![String::new()];
+
+                for character in value.chars() {
+                    if chunks.last().map(|chunk| chunk.len()).unwrap_or(0) + character.len_utf8()
+                        > TXT_CHUNK_MAXIMUM
+                    {
+                        chunks.push(String::new());
+                    }
+
+                    if let Some(chunk) = chunks.last_mut() {
+                        chunk.push(character);
+                    }
+                }
+
+                chunks
+                    .iter()
+                    .map(|chunk| {
+                        format!("\"{}\"", chunk.replace("\\", "\\\\").replace("\"", "\\\""))
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            }
+        }
+    }
+
+    fn map_record_set(
+        line: usize,
+        zone_origin: &TrustName,
+        zone_ttl: u32,
+        ttl_inherited: bool,
+        record_set: &RecordSet,
+        records: &mut BTreeMap<(String, &'static str), StoreRecord>,
+        parsed: &mut ZoneFileParsed,
+    ) {
+        let owner = record_set.name();
+        let record_type_trust = record_set.record_type();
+
+        if zone_origin.zone_of(owner) == false {
+            parsed.unsupported.push(ZoneFileIssue::unsupported(
+                line,
+                owner,
+                &record_type_trust,
+                "name is outside of zone",
+            ));
+
+            return;
+        }
+
+        // Notice: SOA and NS records are served from configuration, and thus cannot be imported.
+        let record_type = match (
+            record_type_trust,
+            RecordType::from_trust(&record_type_trust),
+        ) {
+            (TrustRecordType::SOA, _) | (TrustRecordType::NS, _) => {
+                parsed.unsupported.push(ZoneFileIssue::unsupported(
+                    line,
+                    owner,
+                    &record_type_trust,
+                    "record type is served from configuration",
+                ));
+
+                return;
+            }
+            (_, Some(record_type)) => record_type,
+            (_, None) => {
+                parsed.unsupported.push(ZoneFileIssue::unsupported(
+                    line,
+                    owner,
+                    &record_type_trust,
+                    "record type is not supported",
+                ));
+
+                return;
+            }
+        };
+
+        let record_name = match RecordName::from_trust(zone_origin, owner) {
+            Some(record_name) => record_name,
+            None => {
+                parsed.unsupported.push(ZoneFileIssue::unsupported(
+                    line,
+                    owner,
+                    &record_type_trust,
+                    "record name is not supported",
+                ));
+
+                return;
+            }
+        };
+
+        let record = records
+            .entry((record_name.to_str().to_owned(), record_type.to_str()))
+            .or_insert_with(|| StoreRecord {
+                kind: record_type,
+                name: record_name,
+                ttl: None,
+                blackhole: None,
+                regions: None,
+                rescue: None,
+                values: RecordValues::from_list(Vec::new()),
+                version: None,
+            });
+
+        let mut values = record.values.to_vec();
+        let mut merged_count = values.len();
+
+        for record_trust in record_set.records_without_rrsigs() {
+            if let Some(value) = RecordValue::from_trust(record_trust.rdata()) {
+                if values.contains(&value) == false {
+                    values.push(value);
+                }
+            }
+
+            // Records of a set should share the same TTL, pick the lowest one otherwise (records \
+            //   with no TTL get the zone TTL)
+            let record_ttl = if ttl_inherited == true {
+                None
+            } else {
+                Some(record_trust.ttl())
+            };
+
+            record.ttl = if merged_count == 0 {
+                record_ttl
+            } else {
+                match (record.ttl, record_ttl) {
+                    (None, None) => None,
+                    (ttl, record_ttl) => {
+                        Some(ttl.unwrap_or(zone_ttl).min(record_ttl.unwrap_or(zone_ttl)))
+                    }
+                }
+            };
+
+            merged_count += 1;
+        }
+
+        record.values = RecordValues::from_list(values);
+    }
+
+    fn has_explicit_ttl(entry_rest: &str) -> bool {
+        // Notice: the TTL comes either first, or right after the class; unlike TTLs, record \
+        //   types never start with a digit.
+        let mut entry_parts = entry_rest.split_whitespace();
+
+        let entry_part = match entry_parts.next() {
+            Some(entry_part) if RECORD_CLASSES.contains(&entry_part.to_uppercase().as_str()) => {
+                entry_parts.next()
+            }
+            entry_part => entry_part,
+        };
+
+        entry_part
+            .and_then(|entry_part| entry_part.chars().next())
+            .map(|character| character.is_ascii_digit())
+            .unwrap_or(false)
+    }
+
+    fn split_entries(contents: &str) -> Vec<(usize, String)> {
+        let mut entries = Vec::new();
+
+        let mut entry = String::new();
+        let mut entry_line = 0;
+        let (mut depth, mut quoted) = (0, false);
+
+        for (index, line) in contents.lines().enumerate() {
+            if entry.trim().is_empty() == true {
+                entry.clear();
+
+                entry_line = index + 1;
+            }
+
+            let mut escaped = false;
+
+            for character in line.chars() {
+                if escaped == true {
+                    escaped = false;
+                } else {
+                    match character {
+                        '\\' => escaped = true,
+                        '"' => quoted = !quoted,
+                        ';' if quoted == false => break,
+                        '(' if quoted == false => depth += 1,
+                        ')' if quoted == false && depth > 0 => depth -= 1,
+                        _ => {}
+                    }
+                }
+
+                entry.push(character);
+            }
+
+            // Entry spans over multiple lines? (ie. parentheses or quotes are not closed yet)
+            if depth > 0 || quoted == true {
+                entry.push(' ');
+            } else {
+                if entry.trim().is_empty() == false {
+                    entries.push((entry_line, entry.to_owned()));
+                }
+
+                entry.clear();
+            }
+        }
+
+        // Unterminated entry? (pass it as-is, so that the parser reports an error)
+        if entry.trim().is_empty() == false {
+            entries.push((entry_line, entry));
+        }
+
+        entries
+    }
+}
+
+impl ZoneFileIssue {
+    pub fn error(line: usize, reason: String) -> ZoneFileIssue {
+        ZoneFileIssue {
+            line: line,
+            name: None,
+            _type: None,
+            reason: reason,
+        }
+    }
+
+    pub fn invalid(name: &RecordName, record_type: &RecordType, reason: &str) -> ZoneFileIssue {
+        ZoneFileIssue {
+            line: 0,
+            name: Some(name.to_str().to_owned()),
+            _type: Some(record_type.to_str().to_owned()),
+            reason: reason.to_owned(),
+        }
+    }
+
+    fn unsupported(
+        line: usize,
+        name: &TrustName,
+        record_type: &TrustRecordType,
+        reason: &str,
+    ) -> ZoneFileIssue {
+        ZoneFileIssue {
+            line: line,
+            name: Some(name.to_string()),
+            _type: Some(record_type.to_string()),
+            reason: reason.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> ZoneFileParsed {
+        ZoneFile::parse(
+            &ZoneName::from_str("example.com").expect("invalid test zone"),
+            contents,
+        )
+    }
+
+    fn values(record: &StoreRecord) -> Vec<&str> {
+        record.values.iter().map(|value| value.to_str()).collect()
+    }
+
+    #[test]
+    fn it_parses_multi_line_records() {
+        let parsed = parse("mail 300 IN MX ( 10\n    mx.example.org. ) ; mail exchange\n");
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name.to_str(), "mail.@");
+        assert_eq!(parsed.records[0].kind, RecordType::MX);
+        assert_eq!(parsed.records[0].ttl, Some(300));
+        assert_eq!(values(&parsed.records[0]), vec!["10 mx.example.org"]);
+    }
+
+    #[test]
+    fn it_keeps_quoted_semicolons() {
+        let parsed = parse("@ IN TXT \"v=spf1 a; -all\" ; comment\n");
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name.to_str(), "@");
+        assert_eq!(values(&parsed.records[0]), vec!["v=spf1 a; -all"]);
+    }
+
+    #[test]
+    fn it_applies_default_ttl() {
+        let parsed = parse("a IN A 192.0.2.1\n$TTL 120\nb IN A 192.0.2.2\n");
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 2);
+
+        // Notice: records defined before any '$TTL' directive inherit the configured zone TTL, \
+        //   thus they are stored with no TTL
+        assert_eq!(parsed.records[0].name.to_str(), "a.@");
+        assert_eq!(parsed.records[0].ttl, None);
+        assert_eq!(parsed.records[1].name.to_str(), "b.@");
+        assert_eq!(parsed.records[1].ttl, Some(120));
+    }
+
+    #[test]
+    fn it_keeps_explicit_ttl() {
+        let parsed = parse(
+            "$TTL 3600
+a IN A 192.0.2.1
+b 3600 IN A 192.0.2.2
+c IN 300 A 192.0.2.3
+",
+        );
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 3);
+        assert_eq!(parsed.records[0].ttl, None);
+        assert_eq!(parsed.records[1].ttl, Some(3600));
+        assert_eq!(parsed.records[2].ttl, Some(300));
+    }
+
+    #[test]
+    fn it_merges_record_sets() {
+        let parsed = parse("www 600 IN A 192.0.2.1\n    300 IN A 192.0.2.2\nwww IN A 192.0.2.1\n");
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name.to_str(), "www.@");
+        assert_eq!(parsed.records[0].ttl, Some(300));
+        assert_eq!(values(&parsed.records[0]), vec!["192.0.2.1", "192.0.2.2"]);
+    }
+
+    #[test]
+    fn it_skips_soa_and_ns_records() {
+        let parsed = parse(concat!(
+            "@ IN SOA ns1.example.com. hostmaster.example.com. ( 1 3600 600 86400 60 )\n",
+            "@ IN NS ns1.example.com.\n",
+            "www IN A 192.0.2.1\n"
+        ));
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.unsupported.len(), 2);
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].kind, RecordType::A);
+    }
+
+    #[test]
+    fn it_round_trips_long_txt_values() {
+        let zone_name = ZoneName::from_str("example.com").expect("invalid test zone");
+        let value = format!("v=DKIM1; k=rsa; p={}", "A".repeat(600));
+
+        let record = serde_json::from_str::<StoreRecord>(&format!(
+            r#"{{"kind":"txt","name":"dkim.@","ttl":300,"values":["{}"]}}"#,
+            value
+        ))
+        .expect("invalid test record");
+
+        let parsed = parse(&ZoneFile::render(&zone_name, &[record]));
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name.to_str(), "dkim.@");
+        assert_eq!(parsed.records[0].ttl, Some(300));
+        assert_eq!(values(&parsed.records[0]), vec![value.as_str()]);
+    }
+
+    #[test]
+    fn it_round_trips_records_with_no_ttl() {
+        let zone_name = ZoneName::from_str("example.com").expect("invalid test zone");
+
+        let record = serde_json::from_str::<StoreRecord>(
+            r#"{"kind":"a","name":"www.@","values":["192.0.2.1"]}"#,
+        )
+        .expect("invalid test record");
+
+        let parsed = parse(&ZoneFile::render(&zone_name, &[record]));
+
+        assert!(parsed.errors.is_empty());
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].name.to_str(), "www.@");
+        assert_eq!(parsed.records[0].ttl, None);
+    }
+}
@@ -0,0 +1,361 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::record_guard::RecordGuard;
+use crate::dns::record::{RecordName, RecordType};
+use crate::dns::zone::ZoneName;
+use crate::store::store::{StoreActor, StoreChange, StoreRecord};
+use crate::APP_CONF;
+use crate::APP_STORE;
+
+lazy_static! {
+    pub static ref AUDIT_LOG: AuditLog = AuditLogBuilder::new();
+}
+
+struct AuditLogBuilder;
+
+pub struct AuditLog {
+    file: Mutex<Option<File>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub timestamp: u64,
+    pub action: AuditAction,
+    pub identity: Option<String>,
+    pub label: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub proxied_ip: Option<IpAddr>,
+    pub zone: ZoneName,
+    pub name: RecordName,
+
+    #[serde(rename = "type")]
+    pub kind: RecordType,
+
+    pub before: Option<StoreRecord>,
+    pub after: Option<StoreRecord>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum AuditAction {
+    #[serde(rename = "put")]
+    Put,
+
+    #[serde(rename = "patch")]
+    Patch,
+
+    #[serde(rename = "delete")]
+    Delete,
+
+    #[serde(rename = "rollback")]
+    Rollback,
+
+    #[serde(rename = "batch")]
+    Batch,
+
+    #[serde(rename = "import")]
+    Import,
+}
+
+pub struct AuditQuery<'a> {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub zone: Option<ZoneName>,
+    pub guard: &'a RecordGuard,
+    pub limit: usize,
+}
+
+impl AuditLogBuilder {
+    fn new() -> AuditLog {
+        let file = if APP_CONF.http.audit.file_enable == true {
+            let file_path = &APP_CONF.http.audit.file_path;
+
+            info!("opening audit log file at: {:?}", file_path);
+
+            if let Some(file_directory) = file_path.parent() {
+                if let Err(err) = fs::create_dir_all(file_directory) {
+                    error!("could not create audit log directory: {}", err);
+                }
+            }
+
+            match OpenOptions::new().create(true).append(true).open(file_path) {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    error!("could not open audit log file: {}", err);
+
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        AuditLog {
+            file: Mutex::new(file),
+        }
+    }
+}
+
+impl AuditLog {
+    pub fn is_enabled(&self) -> bool {
+        APP_CONF.http.audit.file_enable == true || APP_CONF.http.audit.redis_enable == true
+    }
+
+    pub fn record(
+        &self,
+        action: AuditAction,
+        zone_name: &ZoneName,
+        actor: &StoreActor,
+        changes: Vec<StoreChange>,
+    ) {
+        // Notice: an event is recorded for each operation of an accepted call, even if it did \
+        //   not change anything (eg. removing a non-existing record).
+        if self.is_enabled() == false {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let mut payloads = Vec::new();
+
+        for change in changes {
+            let event = AuditEvent {
+                timestamp: timestamp,
+                action: action,
+                identity: actor.identity.to_owned(),
+                label: actor.label.to_owned(),
+                ip: actor.ip,
+                proxied_ip: actor.proxied_ip,
+                zone: zone_name.to_owned(),
+                name: change.name,
+                kind: change.kind,
+                before: change.before,
+                after: change.after,
+            };
+
+            match serde_json::to_string(&event) {
+                Ok(payload) => payloads.push(payload),
+                Err(err) => error!("could not encode audit event: {}", err),
+            }
+        }
+
+        // Notice: failing to write an audit event does not fail the change it stands for, as \
+        //   the change was already applied at this point (the failure gets logged though).
+        if APP_CONF.http.audit.file_enable == true {
+            self.write_file(&payloads);
+        }
+
+        if APP_CONF.http.audit.redis_enable == true {
+            self.write_redis(&payloads);
+        }
+    }
+
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, ()> {
+        // Notice: the Redis stream is preferred when enabled, as it is shared between all \
+        //   replicas (whereas each replica has its own audit log file).
+        if APP_CONF.http.audit.redis_enable == true {
+            self.query_redis(query)
+        } else if APP_CONF.http.audit.file_enable == true {
+            self.query_file(query)
+        } else {
+            Err(())
+        }
+    }
+
+    fn write_file(&self, payloads: &[String]) {
+        match *self.file.lock().unwrap() {
+            Some(ref mut file) => {
+                for payload in payloads {
+                    if let Err(err) = writeln!(file, "{}", payload) {
+                        error!("could not write audit event to file: {}", err);
+                    }
+                }
+            }
+            None => error!("could not write audit event to file: file is not open"),
+        }
+    }
+
+    fn write_redis(&self, payloads: &[String]) {
+        match APP_STORE.remote() {
+            Some(remote) => {
+                for payload in payloads {
+                    if let Err(err) = remote.stream_append(
+                        &APP_CONF.http.audit.redis_stream,
+                        APP_CONF.http.audit.redis_stream_max_length,
+                        payload,
+                    ) {
+                        error!("could not write audit event to redis stream: {:?}", err);
+                    }
+                }
+            }
+            None => error!("could not write audit event to redis stream: store is not redis"),
+        }
+    }
+
+    fn query_file(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, ()> {
+        let file = File::open(&APP_CONF.http.audit.file_path).map_err(|err| {
+            error!("could not open audit log file for reading: {}", err);
+        })?;
+
+        // Notice: events are appended to the file, thus the whole file is read, and only the \
+        //   most recent matching events are kept.
+        let mut events = VecDeque::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|err| {
+                error!("could not read audit log file: {}", err);
+            })?;
+
+            // Notice: a line may be partially written if the server crashed while writing it
+            match serde_json::from_str::<AuditEvent>(&line) {
+                Ok(event) => {
+                    if event.matches(query) == true {
+                        events.push_front(event);
+
+                        if events.len() > query.limit {
+                            events.pop_back();
+                        }
+                    }
+                }
+                Err(err) => warn!("skipped invalid audit log file line: {}", err),
+            }
+        }
+
+        Ok(events.into_iter().collect())
+    }
+
+    fn query_redis(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, ()> {
+        let remote = APP_STORE.remote().ok_or(())?;
+
+        // Notice: stream entry identifiers start with the time they were added at (in \
+        //   milliseconds), thus a time range maps to an identifier range.
+        let start_id = query
+            .from
+            .map(|from| from.saturating_mul(1000).to_string())
+            .unwrap_or("-".to_owned());
+        let mut end_id = query
+            .to
+            .map(|to| to.saturating_mul(1000).saturating_add(999).to_string())
+            .unwrap_or("+".to_owned());
+
+        let mut events = Vec::new();
+
+        // Notice: entries are read page by page, from the most recent to the oldest, as some of \
+        //   them may be filtered out (eg. events from another zone).
+        loop {
+            let entries = remote
+                .stream_range_reverse(
+                    &APP_CONF.http.audit.redis_stream,
+                    &start_id,
+                    &end_id,
+                    query.limit,
+                )
+                .map_err(|err| {
+                    error!("could not read audit events from redis stream: {:?}", err);
+                })?;
+
+            let entries_count = entries.len();
+
+            for (entry_id, payload) in entries {
+                match serde_json::from_str::<AuditEvent>(&payload) {
+                    Ok(event) => {
+                        if event.matches(query) == true {
+                            events.push(event);
+
+                            if events.len() >= query.limit {
+                                return Ok(events);
+                            }
+                        }
+                    }
+                    Err(err) => warn!("skipped invalid audit stream entry: {}", err),
+                }
+
+                // Oldest possible entry reached? (there is no entry before it)
+                end_id = match Self::previous_entry_id(&entry_id) {
+                    Some(previous_entry_id) => previous_entry_id,
+                    None => return Ok(events),
+                };
+            }
+
+            if entries_count < query.limit {
+                return Ok(events);
+            }
+        }
+    }
+
+    fn previous_entry_id(entry_id: &str) -> Option<String> {
+        // Notice: stream entry identifiers are formatted as '<milliseconds>-<sequence>'
+        let mut parts = entry_id.splitn(2, '-');
+
+        match (
+            parts.next().and_then(|part| part.parse::<u64>().ok()),
+            parts.next().and_then(|part| part.parse::<u64>().ok()),
+        ) {
+            (Some(millis), Some(0)) if millis > 0 => {
+                Some(format!("{}-{}", millis - 1, u64::max_value()))
+            }
+            (Some(millis), Some(sequence)) if sequence > 0 => {
+                Some(format!("{}-{}", millis, sequence - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl AuditEvent {
+    fn matches(&self, query: &AuditQuery) -> bool {
+        if let Some(from) = query.from {
+            if self.timestamp < from {
+                return false;
+            }
+        }
+
+        if let Some(to) = query.to {
+            if self.timestamp > to {
+                return false;
+            }
+        }
+
+        if let Some(ref zone) = query.zone {
+            if &self.zone != zone {
+                return false;
+            }
+        }
+
+        // Notice: events on records the token is not allowed on are filtered out
+        query.guard.allows_name(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_steps_back_stream_entry_ids() {
+        assert_eq!(
+            AuditLog::previous_entry_id("1700000000000-2"),
+            Some("1700000000000-1".to_owned())
+        );
+        assert_eq!(
+            AuditLog::previous_entry_id("1700000000000-0"),
+            Some("1699999999999-18446744073709551615".to_owned())
+        );
+        assert_eq!(AuditLog::previous_entry_id("0-0"), None);
+        assert_eq!(AuditLog::previous_entry_id("invalid"), None);
+    }
+}
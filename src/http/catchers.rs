@@ -4,13 +4,22 @@
 // Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+use rocket::Request;
 use rocket_contrib::json::Json;
 
+use super::record_guard::RecordGuardDenial;
+
 #[derive(Serialize)]
 pub struct CatcherResponse {
     error: &'static str,
 }
 
+#[derive(Serialize)]
+pub struct CatcherReasonResponse {
+    error: &'static str,
+    reason: Option<String>,
+}
+
 #[catch(400)]
 pub fn bad_request() -> Json<CatcherResponse> {
     Json(CatcherResponse {
@@ -26,8 +35,13 @@ pub fn unauthorized() -> Json<CatcherResponse> {
 }
 
 #[catch(403)]
-pub fn forbidden() -> Json<CatcherResponse> {
-    Json(CatcherResponse { error: "forbidden" })
+pub fn forbidden(request: &Request) -> Json<CatcherReasonResponse> {
+    Json(CatcherReasonResponse {
+        error: "forbidden",
+        reason: request
+            .local_cache(|| RecordGuardDenial::default())
+            .reason(),
+    })
 }
 
 #[catch(404)]
@@ -49,6 +63,18 @@ pub fn not_acceptable() -> Json<CatcherResponse> {
     })
 }
 
+#[catch(409)]
+pub fn conflict() -> Json<CatcherResponse> {
+    Json(CatcherResponse { error: "conflict" })
+}
+
+#[catch(412)]
+pub fn precondition_failed() -> Json<CatcherResponse> {
+    Json(CatcherResponse {
+        error: "precondition_failed",
+    })
+}
+
 #[catch(413)]
 pub fn payload_too_large() -> Json<CatcherResponse> {
     Json(CatcherResponse {
@@ -56,6 +82,13 @@ pub fn payload_too_large() -> Json<CatcherResponse> {
     })
 }
 
+#[catch(429)]
+pub fn too_many_requests() -> Json<CatcherResponse> {
+    Json(CatcherResponse {
+        error: "too_many_requests",
+    })
+}
+
 #[catch(500)]
 pub fn internal_server_error() -> Json<CatcherResponse> {
     Json(CatcherResponse {
@@ -39,11 +39,24 @@ impl HTTPListen {
                     routes::head_zone_record,
                     routes::get_zone_record,
                     routes::put_zone_record,
+                    routes::patch_zone_record,
+                    routes::patch_zone_record_json,
                     routes::delete_zone_record,
+                    routes::get_zone_record_history,
+                    routes::post_zone_record_rollback,
+                    routes::post_zone_batch,
+                    routes::get_zone_records,
+                    routes::post_zone_import,
+                    routes::get_zone_export,
+                    // Audit
+                    routes::get_audit,
                     // Metrics
                     routes::get_metrics_query_types,
                     routes::get_metrics_query_origins,
                     routes::get_metrics_answer_codes,
+                    routes::get_metrics_answer_stales,
+                    // Cache
+                    routes::get_cache_stats,
                 ],
             )
             .register(catchers![
@@ -53,7 +66,10 @@ impl HTTPListen {
                 catchers::not_found,
                 catchers::method_not_allowed,
                 catchers::not_acceptable,
+                catchers::conflict,
+                catchers::precondition_failed,
                 catchers::payload_too_large,
+                catchers::too_many_requests,
                 catchers::internal_server_error,
             ])
             .launch();
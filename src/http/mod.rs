@@ -4,8 +4,12 @@
 // Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+mod audit;
 mod catchers;
+mod precondition_guard;
 mod record_guard;
 mod routes;
+mod throttle;
 
 pub mod listen;
+pub mod webhook;
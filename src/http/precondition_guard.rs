@@ -0,0 +1,177 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::Outcome;
+
+use crate::dns::record::{RecordName, RecordType};
+use crate::store::store::{StoreCondition, StoreConditionExpect};
+
+pub struct PreconditionGuard {
+    expects: Vec<StoreConditionExpect>,
+}
+
+enum PreconditionTags {
+    Any,
+    List(Vec<PreconditionTag>),
+}
+
+struct PreconditionTag {
+    value: String,
+    weak: bool,
+}
+
+impl PreconditionGuard {
+    pub fn into_conditions(
+        self,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Vec<StoreCondition> {
+        self.expects
+            .into_iter()
+            .map(|expect| StoreCondition {
+                name: record_name.to_owned(),
+                kind: record_type.to_owned(),
+                expect: expect,
+            })
+            .collect()
+    }
+}
+
+impl PreconditionTags {
+    fn parse_from(header: &str) -> Result<PreconditionTags, ()> {
+        let header = header.trim();
+
+        if header == "*" {
+            return Ok(PreconditionTags::Any);
+        }
+
+        let mut tags = Vec::new();
+
+        for tag in header.split(",") {
+            let tag = tag.trim();
+
+            // Notice: empty list elements are allowed by the header grammar (eg. '"a", , "b"')
+            if tag.is_empty() == true {
+                continue;
+            }
+
+            let (tag, weak) = if tag.starts_with("W/") {
+                (&tag[2..], true)
+            } else {
+                (tag, false)
+            };
+
+            if tag.len() < 2 || tag.starts_with("\"") == false || tag.ends_with("\"") == false {
+                return Err(());
+            }
+
+            let value = &tag[1..(tag.len() - 1)];
+
+            if value.contains("\"") == true {
+                return Err(());
+            }
+
+            tags.push(PreconditionTag {
+                value: value.to_owned(),
+                weak: weak,
+            });
+        }
+
+        if tags.is_empty() == true {
+            Err(())
+        } else {
+            Ok(PreconditionTags::List(tags))
+        }
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PreconditionGuard {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<PreconditionGuard, ()> {
+        let mut expects = Vec::new();
+
+        if let Some(if_match_value) = request.headers().get_one("if-match") {
+            match PreconditionTags::parse_from(if_match_value) {
+                Ok(PreconditionTags::Any) => expects.push(StoreConditionExpect::Exists),
+                Ok(PreconditionTags::List(tags)) => {
+                    // Notice: 'If-Match' uses the strong comparison function, thus weak tags \
+                    //   never match.
+                    expects.push(StoreConditionExpect::Versions(
+                        tags.into_iter()
+                            .filter(|tag| tag.weak == false)
+                            .map(|tag| tag.value)
+                            .collect(),
+                    ))
+                }
+                Err(_) => return Outcome::Failure((Status::BadRequest, ())),
+            }
+        }
+
+        if let Some(if_none_match_value) = request.headers().get_one("if-none-match") {
+            match PreconditionTags::parse_from(if_none_match_value) {
+                Ok(PreconditionTags::Any) => expects.push(StoreConditionExpect::Missing),
+                Ok(PreconditionTags::List(tags)) => {
+                    expects.push(StoreConditionExpect::NotVersions(
+                        tags.into_iter().map(|tag| tag.value).collect(),
+                    ))
+                }
+                Err(_) => return Outcome::Failure((Status::BadRequest, ())),
+            }
+        }
+
+        Outcome::Success(PreconditionGuard { expects: expects })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_tags(header: &str) -> Vec<(String, bool)> {
+        match PreconditionTags::parse_from(header) {
+            Ok(PreconditionTags::List(tags)) => {
+                tags.into_iter().map(|tag| (tag.value, tag.weak)).collect()
+            }
+            _ => panic!("could not parse tag list: {}", header),
+        }
+    }
+
+    #[test]
+    fn it_parses_any_tag() {
+        assert!(match PreconditionTags::parse_from(" * ") {
+            Ok(PreconditionTags::Any) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn it_parses_tag_lists() {
+        assert_eq!(parse_tags(r#""a1b2""#), vec![("a1b2".to_owned(), false)]);
+
+        assert_eq!(
+            parse_tags(r#""a1b2", W/"c3d4", , "e5f6""#),
+            vec![
+                ("a1b2".to_owned(), false),
+                ("c3d4".to_owned(), true),
+                ("e5f6".to_owned(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_tags() {
+        assert!(PreconditionTags::parse_from("").is_err());
+        assert!(PreconditionTags::parse_from(" , ").is_err());
+        assert!(PreconditionTags::parse_from("a1b2").is_err());
+        assert!(PreconditionTags::parse_from(r#""a1b2"#).is_err());
+        assert!(PreconditionTags::parse_from(r#""a1"b2""#).is_err());
+        assert!(PreconditionTags::parse_from(r#"W/a1b2"#).is_err());
+        assert!(PreconditionTags::parse_from(r#""a1b2", *"#).is_err());
+    }
+}
@@ -4,14 +4,50 @@
 // Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use base64;
+use bcrypt;
+use hmac::{Hmac, Mac};
+use rand;
 use rocket::http::Status;
 use rocket::request::{self, FromRequest, Request};
 use rocket::Outcome;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use subtle::ConstantTimeEq;
 
+use super::throttle::AUTH_THROTTLE;
+use crate::config::config::{ConfigHTTPToken, ConfigHTTPTokenOperation};
+use crate::dns::record::RecordName;
+use crate::dns::zone::ZoneName;
+use crate::store::store::StoreActor;
 use crate::APP_CONF;
 
-pub struct RecordGuard;
+static RECORD_TOKEN_IDENTITY: &'static str = "record_token";
+
+lazy_static! {
+    static ref TOKEN_HASH_VERIFIED: RwLock<HashMap<String, Vec<u8>>> = RwLock::new(HashMap::new());
+    static ref TOKEN_HASH_DIGEST_KEY: [u8; 32] = rand::random();
+}
+
+type RecordGuardTokenDigest = Hmac<Sha256>;
+
+pub struct RecordGuard {
+    pub identity: Option<String>,
+    pub label: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub proxied_ip: Option<IpAddr>,
+
+    token: Option<&'static ConfigHTTPToken>,
+    denial: RecordGuardDenial,
+}
+
+struct RecordGuardToken;
+
+#[derive(Clone, Default)]
+pub struct RecordGuardDenial(Arc<RwLock<Option<String>>>);
 
 pub struct Authorization {
     pub username: String,
@@ -44,17 +80,308 @@ impl Authorization {
     }
 }
 
+impl RecordGuard {
+    pub fn to_actor(&self) -> StoreActor {
+        StoreActor {
+            identity: self.identity.to_owned(),
+            label: self.label.to_owned(),
+            ip: self.ip,
+            proxied_ip: self.proxied_ip,
+        }
+    }
+
+    pub fn authorize(
+        &self,
+        operation: ConfigHTTPTokenOperation,
+        zone_name: Option<&ZoneName>,
+        record_name: Option<&RecordName>,
+    ) -> Result<(), Status> {
+        // Notice: the global record token is not scoped, thus it is allowed everything
+        let token = match self.token {
+            Some(token) => token,
+            None => return Ok(()),
+        };
+
+        if token.operations.contains(&operation) == false {
+            return self.deny(format!("token is not allowed to: {}", operation.to_str()));
+        }
+
+        if let Some(ref zones) = token.zones {
+            match zone_name {
+                Some(zone_name) => {
+                    if zones.contains(zone_name) == false {
+                        return self.deny(format!(
+                            "token is not allowed on zone: {}",
+                            zone_name.to_str()
+                        ));
+                    }
+                }
+                None => return self.deny("token is not allowed on all zones".to_owned()),
+            }
+        }
+
+        if let Some(record_name) = record_name {
+            if self.allows_name(record_name) == false {
+                return self.deny(format!(
+                    "token is not allowed on record: {}",
+                    record_name.to_str()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn allows_name(&self, record_name: &RecordName) -> bool {
+        match self.token.and_then(|token| token.names.as_ref()) {
+            Some(names) => {
+                let record_name = record_name.to_str();
+
+                names
+                    .iter()
+                    .any(|pattern| Self::match_pattern(&pattern.to_lowercase(), record_name))
+            }
+            None => true,
+        }
+    }
+
+    fn deny(&self, reason: String) -> Result<(), Status> {
+        debug!("denied record request: {}", reason);
+
+        *self.denial.0.write().unwrap() = Some(reason);
+
+        Err(Status::Forbidden)
+    }
+
+    fn match_pattern(pattern: &str, value: &str) -> bool {
+        // Notice: patterns only support the '*' wildcard, which matches any sequence of \
+        //   characters (eg. '_acme-challenge.*' matches '_acme-challenge.@').
+        match pattern.find("*") {
+            Some(wildcard_index) => {
+                let prefix = &pattern[..wildcard_index];
+                let suffix = &pattern[(wildcard_index + 1)..];
+
+                value.starts_with(prefix) == true
+                    && (prefix.len()..=value.len()).any(|split_index| {
+                        value.is_char_boundary(split_index) == true
+                            && Self::match_pattern(suffix, &value[split_index..])
+                    })
+            }
+            None => pattern == value,
+        }
+    }
+}
+
+impl RecordGuardToken {
+    fn find<'t>(
+        tokens: &'t [ConfigHTTPToken],
+        username: &str,
+        password: &str,
+    ) -> Option<&'t ConfigHTTPToken> {
+        // Notice: plain tokens are cheap to compare, thus all of them are compared, though only \
+        //   the token named after the username gets its hash verified.
+        if let Some(named_token) = tokens.iter().find(|token| token.name == username) {
+            if Self::verify(
+                password,
+                named_token.token.as_ref(),
+                named_token.token_hash.as_ref(),
+            ) == true
+            {
+                return Some(named_token);
+            }
+        }
+
+        tokens
+            .iter()
+            .find(|token| Self::verify(password, token.token.as_ref(), None))
+    }
+
+    fn verify(password: &str, token: Option<&String>, token_hash: Option<&String>) -> bool {
+        // Notice: plain tokens are compared in constant time, so that response times do not \
+        //   tell how much of a token was guessed right.
+        let token_matches = token
+            .map(|token| bool::from(token.as_bytes().ct_eq(password.as_bytes())))
+            .unwrap_or(false);
+
+        token_matches == true
+            || token_hash
+                .map(|token_hash| Self::verify_hash(password, token_hash))
+                .unwrap_or(false)
+    }
+
+    fn verify_hash(password: &str, token_hash: &str) -> bool {
+        // Notice: hash verifications are slow and memory-hungry by design, thus successful \
+        //   verifications are cached, keyed by a digest of the password (keyed with a secret \
+        //   that is random for each process). Only the last verified password is kept for each \
+        //   hash, which bounds the cache size to the number of configured hashes.
+        let digest = Self::digest(password);
+
+        let is_cached = TOKEN_HASH_VERIFIED
+            .read()
+            .unwrap()
+            .get(token_hash)
+            .map(|verified_digest| bool::from(verified_digest.ct_eq(&digest)))
+            .unwrap_or(false);
+
+        if is_cached == true {
+            return true;
+        }
+
+        let is_verified = Self::verify_hash_uncached(password, token_hash);
+
+        if is_verified == true {
+            TOKEN_HASH_VERIFIED
+                .write()
+                .unwrap()
+                .insert(token_hash.to_owned(), digest);
+        }
+
+        is_verified
+    }
+
+    fn verify_hash_uncached(password: &str, token_hash: &str) -> bool {
+        if token_hash.starts_with("$argon2") == true {
+            match PasswordHash::new(token_hash) {
+                Ok(token_hash) => Argon2::default()
+                    .verify_password(password.as_bytes(), &token_hash)
+                    .is_ok(),
+                Err(err) => {
+                    error!("could not parse argon2 token hash: {}", err);
+
+                    false
+                }
+            }
+        } else if token_hash.starts_with("$2") == true {
+            match bcrypt::verify(password, token_hash) {
+                Ok(matches) => matches,
+                Err(err) => {
+                    error!("could not verify bcrypt token hash: {}", err);
+
+                    false
+                }
+            }
+        } else {
+            error!("unsupported token hash format (expected argon2 or bcrypt)");
+
+            false
+        }
+    }
+
+    fn digest(password: &str) -> Vec<u8> {
+        let mut digest = RecordGuardTokenDigest::new_from_slice(&*TOKEN_HASH_DIGEST_KEY)
+            .expect("invalid token digest key");
+
+        digest.update(password.as_bytes());
+
+        digest.finalize().into_bytes().to_vec()
+    }
+}
+
+impl RecordGuardDenial {
+    pub fn reason(&self) -> Option<String> {
+        self.0.read().unwrap().to_owned()
+    }
+}
+
 impl<'a, 'r> FromRequest<'a, 'r> for RecordGuard {
     type Error = ();
 
     fn from_request(request: &'a Request<'r>) -> request::Outcome<RecordGuard, ()> {
+        // Notice: the denial reason is stored in the request local cache, so that it can be \
+        //   read back from the forbidden catcher.
+        let denial = request
+            .local_cache(|| RecordGuardDenial::default())
+            .to_owned();
+
+        // Notice: the 'X-Real-IP' header can be set by any client, thus it is only trusted on \
+        //   requests coming from a trusted proxy. The connected peer address is used otherwise.
+        let ip = request.remote().map(|remote| remote.ip());
+
+        let proxied_ip = match ip {
+            Some(ref ip) if APP_CONF.http.trusted_proxies.contains(ip) == true => request.real_ip(),
+            _ => None,
+        };
+
+        let throttle_ip = proxied_ip.or(ip);
+
+        // Notice: clients with too many failed authentication attempts are locked out for a \
+        //   while, whether the credentials they send are valid or not.
+        if let Some(ref throttle_ip) = throttle_ip {
+            if AUTH_THROTTLE.is_locked(throttle_ip) == true {
+                return Outcome::Failure((Status::TooManyRequests, ()));
+            }
+        }
+
         if let Some(authorization_value) = request.headers().get_one("authorization") {
             match Authorization::parse_from("Basic", authorization_value) {
                 Ok(authorization) => {
-                    if authorization.password == APP_CONF.http.record_token {
-                        Outcome::Success(RecordGuard)
+                    // Notice: the username is free-form and chosen by the client, thus it is \
+                    //   only kept as a label, next to the identity of the token that was used \
+                    //   (it is left empty by most API clients).
+                    let label = if authorization.username.is_empty() == false {
+                        Some(authorization.username.to_owned())
+                    } else {
+                        None
+                    };
+
+                    // Notice: hash verifications are slow by design, thus a single token hash gets \
+                    //   verified per request: the hash of the scoped token named after the \
+                    //   username if any, or the hash of the record token otherwise.
+                    let record_token_hash = if APP_CONF
+                        .http
+                        .tokens
+                        .iter()
+                        .any(|token| token.name == authorization.username)
+                        == false
+                    {
+                        APP_CONF.http.record_token_hash.as_ref()
+                    } else {
+                        None
+                    };
+
+                    let guard = if RecordGuardToken::verify(
+                        &authorization.password,
+                        APP_CONF.http.record_token.as_ref(),
+                        record_token_hash,
+                    ) == true
+                    {
+                        Some(RecordGuard {
+                            identity: Some(RECORD_TOKEN_IDENTITY.to_owned()),
+                            label: label,
+                            ip: ip,
+                            proxied_ip: proxied_ip,
+                            token: None,
+                            denial: denial.to_owned(),
+                        })
                     } else {
-                        Outcome::Failure((Status::Forbidden, ()))
+                        RecordGuardToken::find(
+                            &APP_CONF.http.tokens,
+                            &authorization.username,
+                            &authorization.password,
+                        )
+                        .map(|token| RecordGuard {
+                            identity: Some(token.name.to_owned()),
+                            label: label,
+                            ip: ip,
+                            proxied_ip: proxied_ip,
+                            token: Some(token),
+                            denial: denial.to_owned(),
+                        })
+                    };
+
+                    // Notice: a successful attempt does not reset failed attempts, otherwise \
+                    //   a valid token could be used to keep guessing other tokens.
+                    match guard {
+                        Some(guard) => Outcome::Success(guard),
+                        None => {
+                            if let Some(throttle_ip) = throttle_ip {
+                                AUTH_THROTTLE.fail(throttle_ip);
+                            }
+
+                            *denial.0.write().unwrap() = Some("token is not valid".to_owned());
+
+                            Outcome::Failure((Status::Forbidden, ()))
+                        }
                     }
                 }
                 Err(_) => Outcome::Failure((Status::BadRequest, ())),
@@ -64,3 +391,139 @@ impl<'a, 'r> FromRequest<'a, 'r> for RecordGuard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argon2::password_hash::SaltString;
+    use argon2::PasswordHasher;
+
+    fn make_guard(names: Option<&str>) -> RecordGuard {
+        let token = toml::from_str::<ConfigHTTPToken>(&format!(
+            "name = \"test\"\ntoken = \"test\"\n{}",
+            names
+                .map(|names| format!("names = {}", names))
+                .unwrap_or("".to_owned())
+        ))
+        .expect("invalid test token");
+
+        RecordGuard {
+            identity: Some(token.name.to_owned()),
+            label: None,
+            ip: None,
+            proxied_ip: None,
+            token: Some(Box::leak(Box::new(token))),
+            denial: RecordGuardDenial::default(),
+        }
+    }
+
+    fn allows_name(guard: &RecordGuard, record_name: &str) -> bool {
+        guard.allows_name(&RecordName::from_str(record_name).expect("invalid test name"))
+    }
+
+    #[test]
+    fn it_matches_patterns() {
+        assert!(RecordGuard::match_pattern("www.@", "www.@"));
+        assert!(RecordGuard::match_pattern("www.@", "api.@") == false);
+        assert!(RecordGuard::match_pattern("*", ""));
+        assert!(RecordGuard::match_pattern("*", "www.@"));
+        assert!(RecordGuard::match_pattern(
+            "_acme-challenge.*",
+            "_acme-challenge.@"
+        ));
+        assert!(RecordGuard::match_pattern(
+            "_acme-challenge.*",
+            "_acme-challenge.www.@"
+        ));
+        assert!(RecordGuard::match_pattern("_acme-challenge.*", "www.@") == false);
+        assert!(RecordGuard::match_pattern("*.@", "a.b.@"));
+        assert!(RecordGuard::match_pattern("*.@", "@") == false);
+        assert!(RecordGuard::match_pattern("a*b*c", "a-b-b-c"));
+        assert!(RecordGuard::match_pattern("a*b*c", "a-c-b") == false);
+        assert!(RecordGuard::match_pattern("*é.@", "café.@"));
+    }
+
+    #[test]
+    fn it_allows_all_names_without_restrictions() {
+        assert!(allows_name(&make_guard(None), "www.@"));
+        assert!(allows_name(&make_guard(None), "@"));
+    }
+
+    #[test]
+    fn it_allows_restricted_names() {
+        let guard = make_guard(Some(r#"["_acme-challenge.*", "WWW.@"]"#));
+
+        assert!(allows_name(&guard, "_acme-challenge.@"));
+        assert!(allows_name(&guard, "_acme-challenge.api.@"));
+        assert!(allows_name(&guard, "www.@"));
+        assert!(allows_name(&guard, "api.@") == false);
+        assert!(allows_name(&guard, "@") == false);
+    }
+
+    #[test]
+    fn it_verifies_plain_tokens() {
+        let token = "secret".to_owned();
+
+        assert!(RecordGuardToken::verify("secret", Some(&token), None));
+        assert!(RecordGuardToken::verify("secre", Some(&token), None) == false);
+        assert!(RecordGuardToken::verify("secrets", Some(&token), None) == false);
+        assert!(RecordGuardToken::verify("secret", None, None) == false);
+    }
+
+    #[test]
+    fn it_verifies_bcrypt_hashes() {
+        let token_hash = bcrypt::hash("secret", 4).expect("could not hash test token");
+
+        // Notice: verify twice, as the second verification is served from cache
+        for _ in 0..2 {
+            assert!(RecordGuardToken::verify("secret", None, Some(&token_hash)));
+            assert!(RecordGuardToken::verify("wrong", None, Some(&token_hash)) == false);
+        }
+    }
+
+    #[test]
+    fn it_verifies_argon2_hashes() {
+        let salt = SaltString::from_b64("c29tZXNhbHRzb21lc2FsdA").expect("invalid test salt");
+        let token_hash = Argon2::default()
+            .hash_password(b"secret", &salt)
+            .expect("could not hash test token")
+            .to_string();
+
+        for _ in 0..2 {
+            assert!(RecordGuardToken::verify("secret", None, Some(&token_hash)));
+            assert!(RecordGuardToken::verify("wrong", None, Some(&token_hash)) == false);
+        }
+    }
+
+    #[test]
+    fn it_verifies_hash_of_named_token_only() {
+        let token_hash = bcrypt::hash("secret", 4).expect("could not hash test token");
+
+        let tokens = vec![
+            toml::from_str::<ConfigHTTPToken>(&format!(
+                "name = \"hashed\"\ntoken_hash = \"{}\"",
+                token_hash
+            ))
+            .expect("invalid test token"),
+            toml::from_str::<ConfigHTTPToken>("name = \"plain\"\ntoken = \"plain-secret\"")
+                .expect("invalid test token"),
+        ];
+
+        let find_name = |username: &str, password: &str| {
+            RecordGuardToken::find(&tokens, username, password).map(|token| token.name.as_str())
+        };
+
+        assert_eq!(find_name("hashed", "secret"), Some("hashed"));
+        assert_eq!(find_name("", "secret"), None);
+        assert_eq!(find_name("plain", "secret"), None);
+        assert_eq!(find_name("", "plain-secret"), Some("plain"));
+        assert_eq!(find_name("hashed", "plain-secret"), Some("plain"));
+    }
+
+    #[test]
+    fn it_rejects_unsupported_hashes() {
+        let token_hash = "5ebe2294ecd0e0f08eab7690d2a6ee69".to_owned();
+
+        assert!(RecordGuardToken::verify("secret", None, Some(&token_hash)) == false);
+    }
+}
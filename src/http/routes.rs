@@ -4,18 +4,33 @@
 // Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use rocket::http::Status;
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Form;
+use rocket::response::{content, status};
+use rocket::Data;
 use rocket_contrib::json::Json;
-use std::collections::HashMap;
+use serde_json;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::net::IpAddr;
 
+use super::audit::{AuditAction, AuditEvent, AuditQuery, AUDIT_LOG};
+use super::precondition_guard::PreconditionGuard;
 use super::record_guard::RecordGuard;
+use super::webhook::WEBHOOK_SPOOL;
+use crate::config::config::ConfigHTTPTokenOperation;
 use crate::dns::metrics::{MetricsStoreCountType, MetricsTimespan, MetricsType, METRICS_STORE};
 use crate::dns::record::{RecordBlackhole, RecordName, RecordRegions, RecordType, RecordValues};
 use crate::dns::zone::ZoneName;
-use crate::store::store::StoreRecord;
+use crate::dns::zonefile::{ZoneFile, ZoneFileIssue, ZoneFileParsed};
+use crate::store::cache::{StoreCacheStats, STORE_CACHE};
+use crate::store::store::{
+    StoreActor, StoreBatchOperation, StoreChange, StoreCondition, StoreConditionExpect, StoreError,
+    StoreRecord, StoreRevision,
+};
 use crate::APP_STORE;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct RecordData {
     ttl: Option<u32>,
     blackhole: Option<RecordBlackhole>,
@@ -24,7 +39,53 @@ pub struct RecordData {
     values: RecordValues,
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize)]
+pub struct ZoneBatchData {
+    operations: Vec<ZoneBatchOperation>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action")]
+pub enum ZoneBatchOperation {
+    #[serde(rename = "upsert")]
+    Upsert {
+        name: RecordName,
+
+        #[serde(rename = "type")]
+        _type: RecordType,
+
+        #[serde(flatten)]
+        data: RecordData,
+    },
+
+    #[serde(rename = "delete")]
+    Delete {
+        name: RecordName,
+
+        #[serde(rename = "type")]
+        _type: RecordType,
+    },
+}
+
+#[derive(FromForm)]
+pub struct RecordsListQuery {
+    #[form(field = "type")]
+    _type: Option<String>,
+
+    prefix: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(FromForm)]
+pub struct AuditListQuery {
+    from: Option<u64>,
+    to: Option<u64>,
+    zone: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct RecordGetResponse {
     #[serde(rename = "type")]
     _type: RecordType,
@@ -37,15 +98,136 @@ pub struct RecordGetResponse {
     values: RecordValues,
 }
 
+#[derive(Responder)]
+pub struct RecordGetTaggedResponse {
+    inner: Json<RecordGetResponse>,
+    etag: Header<'static>,
+}
+
+#[derive(Serialize)]
+pub struct RecordHistoryResponse {
+    revisions: Vec<RecordRevisionResponse>,
+}
+
+#[derive(Serialize)]
+pub struct RecordRevisionResponse {
+    revision: u64,
+    timestamp: u64,
+    identity: Option<String>,
+    label: Option<String>,
+    ip: Option<IpAddr>,
+    record: Option<RecordGetResponse>,
+}
+
+#[derive(Serialize)]
+pub struct RecordsListResponse {
+    records: Vec<RecordGetResponse>,
+    total: usize,
+    offset: usize,
+    limit: usize,
+}
+
+#[derive(Serialize)]
+pub struct AuditListResponse {
+    events: Vec<AuditEvent>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ZoneExportData {
+    zone: ZoneName,
+    records: Vec<RecordGetResponse>,
+}
+
+#[derive(Serialize)]
+pub struct ZoneImportResponse {
+    dry_run: bool,
+    changes: Vec<ZoneImportChange>,
+    unsupported: Vec<ZoneFileIssue>,
+    errors: Vec<ZoneFileIssue>,
+}
+
+#[derive(Serialize)]
+pub struct ZoneImportChange {
+    action: ZoneImportAction,
+    record: RecordGetResponse,
+}
+
+#[derive(Serialize)]
+pub enum ZoneImportAction {
+    #[serde(rename = "create")]
+    Create,
+
+    #[serde(rename = "replace")]
+    Replace,
+}
+
 type MetricsGenericGetResponse = HashMap<String, MetricsStoreCountType>;
 
+static RECORDS_LIST_LIMIT_DEFAULT: usize = 100;
+static RECORDS_LIST_LIMIT_MAXIMUM: usize = 1000;
+static AUDIT_LIST_LIMIT_DEFAULT: usize = 100;
+static AUDIT_LIST_LIMIT_MAXIMUM: usize = 1000;
+static ZONE_IMPORT_SIZE_MAXIMUM: u64 = 4 * 1024 * 1024;
+static ZONE_BATCH_OPERATIONS_MAXIMUM: usize = 1000;
+static RECORD_PATCH_FIELDS: &'static [&'static str] =
+    &["ttl", "blackhole", "regions", "rescue", "values"];
+static RECORD_PATCH_ATTEMPTS_MAXIMUM: u8 = 3;
+
+impl From<RecordGetResponse> for StoreRecord {
+    fn from(record: RecordGetResponse) -> Self {
+        StoreRecord {
+            kind: record._type,
+            name: record.name,
+            ttl: record.ttl,
+            blackhole: record.blackhole,
+            regions: record.regions,
+            rescue: record.rescue,
+            values: record.values,
+            version: None,
+        }
+    }
+}
+
+impl From<StoreRevision> for RecordRevisionResponse {
+    fn from(revision: StoreRevision) -> Self {
+        RecordRevisionResponse {
+            revision: revision.revision,
+            timestamp: revision.timestamp,
+            identity: revision.identity,
+            label: revision.label,
+            ip: revision.ip,
+            record: revision.record.map(RecordGetResponse::from),
+        }
+    }
+}
+
+impl From<StoreRecord> for RecordGetResponse {
+    fn from(record: StoreRecord) -> Self {
+        RecordGetResponse {
+            _type: record.kind,
+            name: record.name,
+            ttl: record.ttl,
+            blackhole: record.blackhole,
+            regions: record.regions,
+            rescue: record.rescue,
+            values: record.values,
+        }
+    }
+}
+
 #[head("/zone/<zone_name>/record/<record_name>/<record_type>")]
 pub fn head_zone_record(
-    _auth: RecordGuard,
+    auth: RecordGuard,
     zone_name: ZoneName,
     record_name: RecordName,
     record_type: RecordType,
 ) -> Result<(), Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Read,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
     APP_STORE
         .check(&zone_name, &record_name, &record_type)
         .or(Err(Status::NotFound))
@@ -53,23 +235,22 @@ pub fn head_zone_record(
 
 #[get("/zone/<zone_name>/record/<record_name>/<record_type>")]
 pub fn get_zone_record(
-    _auth: RecordGuard,
+    auth: RecordGuard,
     zone_name: ZoneName,
     record_name: RecordName,
     record_type: RecordType,
-) -> Result<Json<RecordGetResponse>, Status> {
+) -> Result<RecordGetTaggedResponse, Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Read,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
     APP_STORE
         .get(&zone_name, &record_name, &record_type)
-        .map(|record| {
-            Json(RecordGetResponse {
-                _type: record.kind,
-                name: record.name,
-                ttl: record.ttl,
-                blackhole: record.blackhole,
-                regions: record.regions,
-                rescue: record.rescue,
-                values: record.values,
-            })
+        .map(|record| RecordGetTaggedResponse {
+            etag: Header::new("ETag", format!("\"{}\"", record.to_version())),
+            inner: Json(RecordGetResponse::from(record)),
         })
         .or(Err(Status::NotFound))
 }
@@ -80,16 +261,26 @@ pub fn get_zone_record(
     format = "application/json"
 )]
 pub fn put_zone_record(
-    _auth: RecordGuard,
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
     zone_name: ZoneName,
     record_name: RecordName,
     record_type: RecordType,
     data: Json<RecordData>,
 ) -> Result<(), Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Write,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
+    let conditions = precondition.into_conditions(&record_name, &record_type);
+    let actor = auth.to_actor();
+
     APP_STORE
-        .set(
+        .batch(
             &zone_name,
-            StoreRecord {
+            vec![StoreBatchOperation::Set(StoreRecord {
                 kind: record_type,
                 name: record_name,
                 ttl: data.ttl,
@@ -97,29 +288,679 @@ pub fn put_zone_record(
                 regions: data.regions.to_owned(),
                 rescue: data.rescue.to_owned(),
                 values: data.values.to_owned(),
-            },
+                version: None,
+            })],
+            conditions,
+            &actor,
         )
-        .or(Err(Status::ServiceUnavailable))
+        .map(|changes| notify_changes(AuditAction::Put, &zone_name, &actor, changes))
+        .map_err(|err| match err {
+            StoreError::Conflict => Status::PreconditionFailed,
+            _ => Status::ServiceUnavailable,
+        })
+}
+
+#[patch(
+    "/zone/<zone_name>/record/<record_name>/<record_type>",
+    data = "<data>",
+    format = "application/merge-patch+json"
+)]
+pub fn patch_zone_record(
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
+    zone_name: ZoneName,
+    record_name: RecordName,
+    record_type: RecordType,
+    data: Json<serde_json::Value>,
+) -> Result<(), Status> {
+    apply_zone_record_patch(
+        auth,
+        precondition,
+        zone_name,
+        record_name,
+        record_type,
+        data.into_inner(),
+    )
+}
+
+#[patch(
+    "/zone/<zone_name>/record/<record_name>/<record_type>",
+    data = "<data>",
+    format = "application/json"
+)]
+pub fn patch_zone_record_json(
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
+    zone_name: ZoneName,
+    record_name: RecordName,
+    record_type: RecordType,
+    data: Json<serde_json::Value>,
+) -> Result<(), Status> {
+    // Notice: plain JSON bodies are accepted as well, and get applied as merge patches.
+    apply_zone_record_patch(
+        auth,
+        precondition,
+        zone_name,
+        record_name,
+        record_type,
+        data.into_inner(),
+    )
+}
+
+fn apply_zone_record_patch(
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
+    zone_name: ZoneName,
+    record_name: RecordName,
+    record_type: RecordType,
+    patch: serde_json::Value,
+) -> Result<(), Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Write,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
+    // Only record data fields can be patched (the record name and type cannot be changed)
+    match patch.as_object() {
+        Some(patch_fields) => {
+            if patch_fields
+                .keys()
+                .any(|field| RECORD_PATCH_FIELDS.contains(&field.as_str()) == false)
+                == true
+            {
+                return Err(Status::BadRequest);
+            }
+        }
+        None => return Err(Status::BadRequest),
+    }
+
+    let conditions = precondition.into_conditions(&record_name, &record_type);
+
+    // Notice: the patch is merged into the current record, which is then written only if it \
+    //   was not changed in the meantime. If it was, the merge is attempted again over the \
+    //   updated record, so that concurrent patches never overwrite each other.
+    for _ in 0..RECORD_PATCH_ATTEMPTS_MAXIMUM {
+        let record = match APP_STORE.get_fresh(&zone_name, &record_name, &record_type) {
+            Ok(record) => record,
+            Err(StoreError::NotFound) => {
+                // Conditions requested by the client expect the record to exist? (eg. 'If-Match')
+                return if conditions
+                    .iter()
+                    .all(|condition| condition.expect.holds(None))
+                    == true
+                {
+                    Err(Status::NotFound)
+                } else {
+                    Err(Status::PreconditionFailed)
+                };
+            }
+            Err(_) => return Err(Status::ServiceUnavailable),
+        };
+
+        // Check conditions requested by the client against the current record
+        // Notice: they do not need to be checked again upon writing, as the record is then \
+        //   expected to still be at this very version.
+        if conditions
+            .iter()
+            .all(|condition| condition.expect.holds(Some(record.to_version())))
+            == false
+        {
+            return Err(Status::PreconditionFailed);
+        }
+
+        let mut document = serde_json::to_value(RecordData {
+            ttl: record.ttl,
+            blackhole: record.blackhole.to_owned(),
+            regions: record.regions.to_owned(),
+            rescue: record.rescue.to_owned(),
+            values: record.values.to_owned(),
+        })
+        .or(Err(Status::InternalServerError))?;
+
+        merge_patch(&mut document, &patch);
+
+        let data = serde_json::from_value::<RecordData>(document).or(Err(Status::BadRequest))?;
+
+        let patched_record = StoreRecord {
+            kind: record_type.to_owned(),
+            name: record_name.to_owned(),
+            ttl: data.ttl,
+            blackhole: data.blackhole,
+            regions: data.regions,
+            rescue: data.rescue,
+            values: data.values,
+            version: None,
+        };
+
+        // Values must be valid for the record type (eg. an IPv4 address for an 'A' record)
+        for value in patched_record.list_record_values() {
+            if value.to_trust(&patched_record.kind).is_err() == true {
+                return Err(Status::BadRequest);
+            }
+        }
+
+        let patch_conditions = vec![StoreCondition {
+            name: record_name.to_owned(),
+            kind: record_type.to_owned(),
+            expect: StoreConditionExpect::Versions(vec![record.to_version().to_owned()]),
+        }];
+
+        let actor = auth.to_actor();
+
+        match APP_STORE.batch(
+            &zone_name,
+            vec![StoreBatchOperation::Set(patched_record)],
+            patch_conditions,
+            &actor,
+        ) {
+            Ok(changes) => {
+                notify_changes(AuditAction::Patch, &zone_name, &actor, changes);
+
+                return Ok(());
+            }
+            Err(StoreError::Conflict) => {
+                // Record was changed in the meantime, merge the patch again over the updated \
+                //   record (client conditions get checked again against it)
+                debug!("record patch conflicted, attempting again");
+            }
+            Err(_) => return Err(Status::ServiceUnavailable),
+        }
+    }
+
+    Err(Status::Conflict)
 }
 
 #[delete("/zone/<zone_name>/record/<record_name>/<record_type>")]
 pub fn delete_zone_record(
-    _auth: RecordGuard,
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
     zone_name: ZoneName,
     record_name: RecordName,
     record_type: RecordType,
 ) -> Result<(), Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Delete,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
+    let conditions = precondition.into_conditions(&record_name, &record_type);
+    let actor = auth.to_actor();
+
     APP_STORE
-        .remove(&zone_name, &record_name, &record_type)
+        .batch(
+            &zone_name,
+            vec![StoreBatchOperation::Remove(record_name, record_type)],
+            conditions,
+            &actor,
+        )
+        .map(|changes| notify_changes(AuditAction::Delete, &zone_name, &actor, changes))
+        .map_err(|err| match err {
+            StoreError::Conflict => Status::PreconditionFailed,
+            _ => Status::ServiceUnavailable,
+        })
+}
+
+#[get("/zone/<zone_name>/record/<record_name>/<record_type>/history")]
+pub fn get_zone_record_history(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    record_name: RecordName,
+    record_type: RecordType,
+) -> Result<Json<RecordHistoryResponse>, Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Read,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
+    APP_STORE
+        .history(&zone_name, &record_name, &record_type)
+        .map(|revisions| {
+            Json(RecordHistoryResponse {
+                revisions: revisions
+                    .into_iter()
+                    .map(RecordRevisionResponse::from)
+                    .collect(),
+            })
+        })
+        .or(Err(Status::ServiceUnavailable))
+}
+
+#[post("/zone/<zone_name>/record/<record_name>/<record_type>/rollback?<revision>")]
+pub fn post_zone_record_rollback(
+    auth: RecordGuard,
+    precondition: PreconditionGuard,
+    zone_name: ZoneName,
+    record_name: RecordName,
+    record_type: RecordType,
+    revision: u64,
+) -> Result<(), Status> {
+    auth.authorize(
+        ConfigHTTPTokenOperation::Read,
+        Some(&zone_name),
+        Some(&record_name),
+    )?;
+
+    let revision = APP_STORE
+        .history(&zone_name, &record_name, &record_type)
+        .or(Err(Status::ServiceUnavailable))?
+        .into_iter()
+        .find(|entry| entry.revision == revision)
+        .ok_or(Status::NotFound)?;
+
+    // Notice: a revision holds the record as it was before the change it stands for, thus \
+    //   rolling back to a revision undoes this change (and all later ones). A revision with no \
+    //   record stands for the record creation, thus rolling back removes the record.
+    let operation = match revision.record {
+        Some(record) => {
+            auth.authorize(
+                ConfigHTTPTokenOperation::Write,
+                Some(&zone_name),
+                Some(&record_name),
+            )?;
+
+            StoreBatchOperation::Set(record)
+        }
+        None => {
+            auth.authorize(
+                ConfigHTTPTokenOperation::Delete,
+                Some(&zone_name),
+                Some(&record_name),
+            )?;
+
+            StoreBatchOperation::Remove(record_name.to_owned(), record_type.to_owned())
+        }
+    };
+
+    let actor = auth.to_actor();
+
+    APP_STORE
+        .batch(
+            &zone_name,
+            vec![operation],
+            precondition.into_conditions(&record_name, &record_type),
+            &actor,
+        )
+        .map(|changes| notify_changes(AuditAction::Rollback, &zone_name, &actor, changes))
+        .map_err(|err| match err {
+            StoreError::Conflict => Status::PreconditionFailed,
+            _ => Status::ServiceUnavailable,
+        })
+}
+
+#[post(
+    "/zone/<zone_name>/batch",
+    data = "<data>",
+    format = "application/json"
+)]
+pub fn post_zone_batch(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    data: Json<ZoneBatchData>,
+) -> Result<(), Status> {
+    let data = data.into_inner();
+
+    if data.operations.is_empty() == true || data.operations.len() > ZONE_BATCH_OPERATIONS_MAXIMUM {
+        return Err(Status::BadRequest);
+    }
+
+    // Validate all operations first (nothing gets applied if any operation is invalid)
+    let mut touched = HashSet::new();
+    let mut operations = Vec::new();
+
+    for operation in data.operations {
+        let operation = match operation {
+            ZoneBatchOperation::Upsert { name, _type, data } => {
+                StoreBatchOperation::Set(StoreRecord {
+                    kind: _type,
+                    name: name,
+                    ttl: data.ttl,
+                    blackhole: data.blackhole,
+                    regions: data.regions,
+                    rescue: data.rescue,
+                    values: data.values,
+                    version: None,
+                })
+            }
+            ZoneBatchOperation::Delete { name, _type } => StoreBatchOperation::Remove(name, _type),
+        };
+
+        let (record_name, record_type) = match operation {
+            StoreBatchOperation::Set(ref record) => {
+                auth.authorize(
+                    ConfigHTTPTokenOperation::Write,
+                    Some(&zone_name),
+                    Some(&record.name),
+                )?;
+
+                // Values must be valid for the record type (eg. an IPv4 address for an 'A' record)
+                for value in record.list_record_values() {
+                    if value.to_trust(&record.kind).is_err() == true {
+                        return Err(Status::BadRequest);
+                    }
+                }
+
+                (&record.name, &record.kind)
+            }
+            StoreBatchOperation::Remove(ref record_name, ref record_type) => {
+                auth.authorize(
+                    ConfigHTTPTokenOperation::Delete,
+                    Some(&zone_name),
+                    Some(record_name),
+                )?;
+
+                (record_name, record_type)
+            }
+        };
+
+        // Notice: a record cannot be touched twice in the same batch, as the outcome would \
+        //   depend on the order of operations.
+        if touched.insert((record_name.to_owned(), record_type.to_owned())) == false {
+            return Err(Status::BadRequest);
+        }
+
+        operations.push(operation);
+    }
+
+    let actor = auth.to_actor();
+
+    APP_STORE
+        .batch(&zone_name, operations, Vec::new(), &actor)
+        .map(|changes| notify_changes(AuditAction::Batch, &zone_name, &actor, changes))
+        .or(Err(Status::ServiceUnavailable))
+}
+
+#[get("/zone/<zone_name>/records?<query..>")]
+pub fn get_zone_records(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    query: Form<RecordsListQuery>,
+) -> Result<Json<RecordsListResponse>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Read, Some(&zone_name), None)?;
+
+    let record_type = match query._type {
+        Some(ref record_type_raw) => {
+            Some(RecordType::from_str(record_type_raw).ok_or(Status::BadRequest)?)
+        }
+        None => None,
+    };
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(RECORDS_LIST_LIMIT_DEFAULT);
+
+    if limit == 0 || limit > RECORDS_LIST_LIMIT_MAXIMUM {
+        return Err(Status::BadRequest);
+    }
+
+    // Notice: record names are stored lowercased, thus the prefix needs to be lowercased too.
+    let name_prefix = query.prefix.as_ref().map(|prefix| prefix.to_lowercase());
+
+    let entries = APP_STORE
+        .index(
+            &zone_name,
+            name_prefix.as_ref().map(|prefix| prefix.as_str()),
+        )
+        .or(Err(Status::ServiceUnavailable))?
+        .into_iter()
+        .filter(|entry| {
+            record_type
+                .as_ref()
+                .map(|record_type| &entry.kind == record_type)
+                .unwrap_or(true)
+        })
+        .filter(|entry| auth.allows_name(&entry.name))
+        .collect::<Vec<_>>();
+
+    let mut records = Vec::new();
+
+    for entry in entries.iter().skip(offset).take(limit) {
+        match APP_STORE.get(&zone_name, &entry.name, &entry.kind) {
+            Ok(record) => records.push(RecordGetResponse::from(record)),
+            Err(StoreError::NotFound) => {}
+            Err(_) => return Err(Status::ServiceUnavailable),
+        }
+    }
+
+    Ok(Json(RecordsListResponse {
+        records: records,
+        total: entries.len(),
+        offset: offset,
+        limit: limit,
+    }))
+}
+
+#[post("/zone/<zone_name>/import?<dry_run>", data = "<data>")]
+pub fn post_zone_import(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    dry_run: Option<bool>,
+    content_type: Option<&ContentType>,
+    data: Data,
+) -> Result<status::Custom<Json<ZoneImportResponse>>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Write, Some(&zone_name), None)?;
+
+    let dry_run = dry_run.unwrap_or(false);
+
+    // Read zone file (up to the maximum size, plus one byte to detect larger files)
+    let mut contents = String::new();
+
+    data.open()
+        .take(ZONE_IMPORT_SIZE_MAXIMUM + 1)
+        .read_to_string(&mut contents)
+        .or(Err(Status::BadRequest))?;
+
+    if contents.len() as u64 > ZONE_IMPORT_SIZE_MAXIMUM {
+        return Err(Status::PayloadTooLarge);
+    }
+
+    // Notice: JSON payloads are zone exports (in JSON format), which hold data that zone files \
+    //   cannot hold (eg. Geo-DNS regions).
+    let parsed = if content_type.map(|content_type| content_type.is_json()) == Some(true) {
+        match serde_json::from_str::<ZoneExportData>(&contents) {
+            Ok(export) => {
+                let mut parsed = ZoneFileParsed {
+                    records: Vec::new(),
+                    unsupported: Vec::new(),
+                    errors: Vec::new(),
+                };
+
+                let mut touched = HashSet::new();
+
+                for record in export.records.into_iter().map(StoreRecord::from) {
+                    // Values must be valid for the record type (eg. an IPv4 address for an 'A' \
+                    //   record), as they are not checked when decoding JSON.
+                    if record
+                        .list_record_values()
+                        .iter()
+                        .any(|value| value.to_trust(&record.kind).is_err())
+                        == true
+                    {
+                        parsed.errors.push(ZoneFileIssue::invalid(
+                            &record.name,
+                            &record.kind,
+                            "record values are not valid for record type",
+                        ));
+                    } else if touched.insert((record.name.to_owned(), record.kind.to_owned()))
+                        == false
+                    {
+                        parsed.errors.push(ZoneFileIssue::invalid(
+                            &record.name,
+                            &record.kind,
+                            "record is defined more than once",
+                        ));
+                    } else {
+                        parsed.records.push(record);
+                    }
+                }
+
+                parsed
+            }
+            Err(err) => ZoneFileParsed {
+                records: Vec::new(),
+                unsupported: Vec::new(),
+                errors: vec![ZoneFileIssue::error(err.line(), err.to_string())],
+            },
+        }
+    } else {
+        ZoneFile::parse(&zone_name, &contents)
+    };
+
+    // Plan changes (ie. tell which records are created, and which are replaced)
+    let mut changes = Vec::new();
+
+    for record in parsed.records.iter() {
+        auth.authorize(
+            ConfigHTTPTokenOperation::Write,
+            Some(&zone_name),
+            Some(&record.name),
+        )?;
+
+        let action = match APP_STORE.check(&zone_name, &record.name, &record.kind) {
+            Ok(_) => ZoneImportAction::Replace,
+            Err(StoreError::NotFound) => ZoneImportAction::Create,
+            Err(_) => return Err(Status::ServiceUnavailable),
+        };
+
+        changes.push(ZoneImportChange {
+            action: action,
+            record: RecordGetResponse::from(record.to_owned()),
+        });
+    }
+
+    // Notice: nothing gets written if any line failed to parse, as a partial import would be \
+    //   hard to recover from. Unsupported records are skipped though, as they are expected (eg. \
+    //   SOA and NS records). Records are written in batches, each batch being written \
+    //   atomically; as importing only sets records, a failed import can be attempted again.
+    let has_errors = parsed.errors.is_empty() == false;
+
+    if dry_run == false && has_errors == false {
+        let actor = auth.to_actor();
+
+        for records in parsed.records.chunks(ZONE_BATCH_OPERATIONS_MAXIMUM) {
+            let operations = records
+                .iter()
+                .map(|record| StoreBatchOperation::Set(record.to_owned()))
+                .collect();
+
+            let changes = APP_STORE
+                .batch(&zone_name, operations, Vec::new(), &actor)
+                .or(Err(Status::ServiceUnavailable))?;
+
+            notify_changes(AuditAction::Import, &zone_name, &actor, changes);
+        }
+    }
+
+    let response_status = if dry_run == false && has_errors == true {
+        Status::UnprocessableEntity
+    } else {
+        Status::Ok
+    };
+
+    Ok(status::Custom(
+        response_status,
+        Json(ZoneImportResponse {
+            dry_run: dry_run,
+            changes: changes,
+            unsupported: parsed.unsupported,
+            errors: parsed.errors,
+        }),
+    ))
+}
+
+#[get("/zone/<zone_name>/export?<format>")]
+pub fn get_zone_export(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    format: Option<String>,
+) -> Result<content::Content<String>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Read, Some(&zone_name), None)?;
+
+    // Notice: records the token is not allowed on are left out of the export
+    let mut records = APP_STORE
+        .list(&zone_name)
+        .or(Err(Status::ServiceUnavailable))?
+        .into_iter()
+        .filter(|record| auth.allows_name(&record.name))
+        .collect::<Vec<_>>();
+
+    // Sort records by name, then by type (so that exports can be compared)
+    records.sort_by(|record_a, record_b| {
+        (record_a.name.to_str(), record_a.kind.to_str())
+            .cmp(&(record_b.name.to_str(), record_b.kind.to_str()))
+    });
+
+    match format
+        .as_ref()
+        .map(|format| format.as_str())
+        .unwrap_or("bind")
+    {
+        "bind" => Ok(content::Content(
+            ContentType::Plain,
+            ZoneFile::render(&zone_name, &records),
+        )),
+        "json" => serde_json::to_string(&ZoneExportData {
+            zone: zone_name,
+            records: records.into_iter().map(RecordGetResponse::from).collect(),
+        })
+        .map(|export| content::Content(ContentType::JSON, export))
+        .or(Err(Status::InternalServerError)),
+        _ => Err(Status::BadRequest),
+    }
+}
+
+#[get("/audit?<query..>")]
+pub fn get_audit(
+    auth: RecordGuard,
+    query: Form<AuditListQuery>,
+) -> Result<Json<AuditListResponse>, Status> {
+    let zone_name = match query.zone {
+        Some(ref zone_name_raw) => {
+            Some(ZoneName::from_str(zone_name_raw).ok_or(Status::BadRequest)?)
+        }
+        None => None,
+    };
+
+    // Notice: tokens scoped to some zones must filter on one of those zones
+    auth.authorize(ConfigHTTPTokenOperation::Audit, zone_name.as_ref(), None)?;
+
+    if AUDIT_LOG.is_enabled() == false {
+        return Err(Status::NotFound);
+    }
+
+    let limit = query.limit.unwrap_or(AUDIT_LIST_LIMIT_DEFAULT);
+
+    if limit == 0 || limit > AUDIT_LIST_LIMIT_MAXIMUM {
+        return Err(Status::BadRequest);
+    }
+
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(Status::BadRequest);
+        }
+    }
+
+    AUDIT_LOG
+        .query(&AuditQuery {
+            from: query.from,
+            to: query.to,
+            zone: zone_name,
+            guard: &auth,
+            limit: limit,
+        })
+        .map(|events| Json(AuditListResponse { events: events }))
         .or(Err(Status::ServiceUnavailable))
 }
 
 #[get("/zone/<zone_name>/metrics/<metrics_timespan>/query/types")]
 pub fn get_metrics_query_types(
-    _auth: RecordGuard,
+    auth: RecordGuard,
     zone_name: ZoneName,
     metrics_timespan: MetricsTimespan,
 ) -> Result<Json<MetricsGenericGetResponse>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Metrics, Some(&zone_name), None)?;
+
     METRICS_STORE
         .aggregate(&zone_name, MetricsType::QueryType, metrics_timespan)
         .ok_or(Status::NotFound)
@@ -128,10 +969,12 @@ pub fn get_metrics_query_types(
 
 #[get("/zone/<zone_name>/metrics/<metrics_timespan>/query/origins")]
 pub fn get_metrics_query_origins(
-    _auth: RecordGuard,
+    auth: RecordGuard,
     zone_name: ZoneName,
     metrics_timespan: MetricsTimespan,
 ) -> Result<Json<MetricsGenericGetResponse>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Metrics, Some(&zone_name), None)?;
+
     METRICS_STORE
         .aggregate(&zone_name, MetricsType::QueryOrigin, metrics_timespan)
         .ok_or(Status::NotFound)
@@ -140,12 +983,139 @@ pub fn get_metrics_query_origins(
 
 #[get("/zone/<zone_name>/metrics/<metrics_timespan>/answer/codes")]
 pub fn get_metrics_answer_codes(
-    _auth: RecordGuard,
+    auth: RecordGuard,
     zone_name: ZoneName,
     metrics_timespan: MetricsTimespan,
 ) -> Result<Json<MetricsGenericGetResponse>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Metrics, Some(&zone_name), None)?;
+
     METRICS_STORE
         .aggregate(&zone_name, MetricsType::AnswerCode, metrics_timespan)
         .ok_or(Status::NotFound)
         .map(|aggregated| Json(aggregated))
 }
+
+#[get("/zone/<zone_name>/metrics/<metrics_timespan>/answer/stales")]
+pub fn get_metrics_answer_stales(
+    auth: RecordGuard,
+    zone_name: ZoneName,
+    metrics_timespan: MetricsTimespan,
+) -> Result<Json<MetricsGenericGetResponse>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Metrics, Some(&zone_name), None)?;
+
+    METRICS_STORE
+        .aggregate(&zone_name, MetricsType::AnswerStale, metrics_timespan)
+        .ok_or(Status::NotFound)
+        .map(|aggregated| Json(aggregated))
+}
+
+#[get("/cache/stats")]
+pub fn get_cache_stats(auth: RecordGuard) -> Result<Json<StoreCacheStats>, Status> {
+    auth.authorize(ConfigHTTPTokenOperation::Metrics, None, None)?;
+
+    Ok(Json(STORE_CACHE.stats()))
+}
+
+fn notify_changes(
+    action: AuditAction,
+    zone_name: &ZoneName,
+    actor: &StoreActor,
+    changes: Vec<StoreChange>,
+) {
+    // Notice: changes are notified once applied, thus a notification failure cannot revert them
+    WEBHOOK_SPOOL.enqueue(zone_name, &changes);
+    AUDIT_LOG.record(action, zone_name, actor, changes);
+}
+
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    // Notice: applies a JSON Merge Patch, as described in RFC 7396
+    if let Some(patch_fields) = patch.as_object() {
+        if target.is_object() == false {
+            *target = serde_json::Value::Object(serde_json::Map::new());
+        }
+
+        if let Some(target_fields) = target.as_object_mut() {
+            for (key, value) in patch_fields {
+                if value.is_null() == true {
+                    target_fields.remove(key);
+                } else {
+                    merge_patch(
+                        target_fields
+                            .entry(key.to_owned())
+                            .or_insert(serde_json::Value::Null),
+                        value,
+                    );
+                }
+            }
+        }
+    } else {
+        *target = patch.to_owned();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(target: &str, patch: &str) -> serde_json::Value {
+        let mut target = serde_json::from_str(target).expect("invalid test target");
+
+        merge_patch(
+            &mut target,
+            &serde_json::from_str(patch).expect("invalid test patch"),
+        );
+
+        target
+    }
+
+    fn value(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).expect("invalid test value")
+    }
+
+    #[test]
+    fn it_merges_patch_fields() {
+        assert_eq!(
+            merge(r#"{"ttl":60,"values":["10.0.0.1"]}"#, r#"{"ttl":120}"#),
+            value(r#"{"ttl":120,"values":["10.0.0.1"]}"#)
+        );
+    }
+
+    #[test]
+    fn it_removes_null_patch_fields() {
+        assert_eq!(
+            merge(r#"{"ttl":60,"rescue":["10.0.0.9"]}"#, r#"{"rescue":null}"#),
+            value(r#"{"ttl":60}"#)
+        );
+        assert_eq!(
+            merge(r#"{"ttl":60}"#, r#"{"missing":null}"#),
+            value(r#"{"ttl":60}"#)
+        );
+    }
+
+    #[test]
+    fn it_merges_nested_patch_objects() {
+        assert_eq!(
+            merge(
+                r#"{"regions":{"nnam":["10.0.0.1"],"weu":["10.0.0.2"]}}"#,
+                r#"{"regions":{"nnam":null,"seas":["10.0.0.3"]}}"#
+            ),
+            value(r#"{"regions":{"weu":["10.0.0.2"],"seas":["10.0.0.3"]}}"#)
+        );
+        assert_eq!(
+            merge(r#"{"regions":null}"#, r#"{"regions":{"weu":["10.0.0.2"]}}"#),
+            value(r#"{"regions":{"weu":["10.0.0.2"]}}"#)
+        );
+    }
+
+    #[test]
+    fn it_replaces_arrays_and_non_object_patches() {
+        assert_eq!(
+            merge(
+                r#"{"values":["10.0.0.1","10.0.0.2"]}"#,
+                r#"{"values":["10.0.0.3"]}"#
+            ),
+            value(r#"{"values":["10.0.0.3"]}"#)
+        );
+        assert_eq!(merge(r#"{"ttl":60}"#, r#"["a"]"#), value(r#"["a"]"#));
+    }
+}
@@ -0,0 +1,142 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::APP_CONF;
+
+lazy_static! {
+    pub static ref AUTH_THROTTLE: AuthThrottle = AuthThrottleBuilder::new();
+}
+
+struct AuthThrottleBuilder;
+
+pub struct AuthThrottle {
+    failures: RwLock<HashMap<IpAddr, AuthThrottleEntry>>,
+}
+
+struct AuthThrottleEntry {
+    count: u32,
+    since: Instant,
+}
+
+impl AuthThrottleBuilder {
+    fn new() -> AuthThrottle {
+        AuthThrottle {
+            failures: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl AuthThrottle {
+    pub fn is_locked(&self, ip: &IpAddr) -> bool {
+        if APP_CONF.http.auth_max_attempts == 0 {
+            return false;
+        }
+
+        self.failures
+            .read()
+            .unwrap()
+            .get(ip)
+            .map(|entry| {
+                entry.is_expired() == false && entry.count >= APP_CONF.http.auth_max_attempts
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn fail(&self, ip: IpAddr) {
+        if APP_CONF.http.auth_max_attempts == 0 {
+            return;
+        }
+
+        let mut failures = self.failures.write().unwrap();
+
+        // Notice: expired entries are cleaned up there, as this is the only place they get \
+        //   inserted from (this bounds memory usage to the number of recently failing clients).
+        failures.retain(|_, entry| entry.is_expired() == false);
+
+        let entry = failures.entry(ip).or_insert_with(|| AuthThrottleEntry {
+            count: 0,
+            since: Instant::now(),
+        });
+
+        entry.count += 1;
+
+        if entry.count == APP_CONF.http.auth_max_attempts {
+            warn!(
+                "locking out client: {} after {} failed authentication attempts",
+                ip, entry.count
+            );
+        }
+    }
+}
+
+impl AuthThrottleEntry {
+    fn is_expired(&self) -> bool {
+        self.since.elapsed() >= Duration::from_secs(APP_CONF.http.auth_lockout_window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fail_times(throttle: &AuthThrottle, ip: IpAddr, times: u32) {
+        for _ in 0..times {
+            throttle.fail(ip);
+        }
+    }
+
+    #[test]
+    fn it_locks_out_after_max_attempts() {
+        let throttle = AuthThrottleBuilder::new();
+        let ip = "10.0.0.1".parse().unwrap();
+
+        fail_times(&throttle, ip, APP_CONF.http.auth_max_attempts - 1);
+        assert!(throttle.is_locked(&ip) == false);
+
+        fail_times(&throttle, ip, 1);
+        assert!(throttle.is_locked(&ip));
+    }
+
+    #[test]
+    fn it_counts_attempts_per_client() {
+        let throttle = AuthThrottleBuilder::new();
+        let (ip_locked, ip_other) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+
+        fail_times(&throttle, ip_locked, APP_CONF.http.auth_max_attempts);
+
+        assert!(throttle.is_locked(&ip_locked));
+        assert!(throttle.is_locked(&ip_other) == false);
+    }
+
+    #[test]
+    fn it_unlocks_after_lockout_window() {
+        let throttle = AuthThrottleBuilder::new();
+        let ip = "10.0.0.1".parse().unwrap();
+
+        fail_times(&throttle, ip, APP_CONF.http.auth_max_attempts);
+        assert!(throttle.is_locked(&ip));
+
+        // Move the first failed attempt back in time, past the lockout window
+        let since = Instant::now()
+            .checked_sub(Duration::from_secs(APP_CONF.http.auth_lockout_window + 1))
+            .expect("monotonic clock is too early to move back");
+
+        if let Some(entry) = throttle.failures.write().unwrap().get_mut(&ip) {
+            entry.since = since;
+        }
+
+        assert!(throttle.is_locked(&ip) == false);
+
+        // Expired entries are cleaned up on next failure, thus counting starts over
+        fail_times(&throttle, ip, 1);
+        assert!(throttle.is_locked(&ip) == false);
+    }
+}
@@ -0,0 +1,589 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use farmhash;
+use hmac::{Hmac, Mac};
+use http_req::request::{Method, Request};
+use http_req::uri::Uri;
+use serde_json;
+use sha2::Sha256;
+use sled::{self, Db, Tree};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io;
+use std::str::{self, FromStr};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::config::{ConfigHTTPWebhookEndpoint, ConfigHTTPWebhookEvent};
+use crate::dns::record::{RecordBlackhole, RecordName, RecordRegions, RecordType, RecordValues};
+use crate::dns::zone::ZoneName;
+use crate::store::store::{StoreChange, StoreRecord};
+use crate::APP_CONF;
+use crate::THREAD_NAME_HTTP_WEBHOOK;
+
+lazy_static! {
+    pub static ref WEBHOOK_SPOOL: WebhookSpool = WebhookSpoolBuilder::new();
+}
+
+type WebhookSignature = Hmac<Sha256>;
+
+const WEBHOOK_DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+const WEBHOOK_DISPATCH_RESPAWN_DELAY: Duration = Duration::from_secs(2);
+const WEBHOOK_QUEUE_PREFIX: &'static str = "endpoint:";
+const WEBHOOK_USERAGENT: &'static str = "constellation (webhook)";
+
+struct WebhookSpoolBuilder;
+
+// Notice: deliveries are spooled in one queue per endpoint, named after the endpoint identity \
+//   (its configured name, or a fingerprint of its URL and secret), so that each endpoint can be \
+//   dispatched to independently, and that pending deliveries follow the endpoint if endpoints \
+//   get reordered in configuration.
+pub struct WebhookSpool {
+    db: Option<Db>,
+    queues: Vec<Tree>,
+}
+
+pub struct WebhookDispatchBuilder;
+pub struct WebhookDispatch;
+
+#[derive(Serialize, Deserialize)]
+struct WebhookDelivery {
+    event: ConfigHTTPWebhookEvent,
+    zone: ZoneName,
+    payload: String,
+    attempts: u32,
+    attempt_at: u64,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    event: ConfigHTTPWebhookEvent,
+    timestamp: u64,
+    zone: &'a ZoneName,
+    name: &'a RecordName,
+
+    #[serde(rename = "type")]
+    kind: &'a RecordType,
+
+    old: Option<WebhookPayloadData<'a>>,
+    new: Option<WebhookPayloadData<'a>>,
+}
+
+#[derive(Serialize)]
+struct WebhookPayloadData<'a> {
+    ttl: Option<u32>,
+    blackhole: &'a Option<RecordBlackhole>,
+    regions: &'a Option<RecordRegions>,
+    rescue: &'a Option<RecordValues>,
+    values: &'a RecordValues,
+}
+
+impl WebhookSpoolBuilder {
+    fn new() -> WebhookSpool {
+        // Notice: the spool is only opened if webhooks are configured, as it is stored on disk
+        let endpoints_count = APP_CONF.http.webhook.endpoints.len();
+
+        let db = if endpoints_count > 0 {
+            info!(
+                "opening webhook spool at: {:?}",
+                APP_CONF.http.webhook.spool_path
+            );
+
+            match sled::open(&APP_CONF.http.webhook.spool_path) {
+                Ok(db) => {
+                    info!("opened webhook spool");
+
+                    Some(db)
+                }
+                Err(err) => panic!("could not open webhook spool: {}", err),
+            }
+        } else {
+            None
+        };
+
+        let queues = match db {
+            Some(ref db) => {
+                let identities = APP_CONF
+                    .http
+                    .webhook
+                    .endpoints
+                    .iter()
+                    .map(Self::endpoint_identity)
+                    .collect::<Vec<String>>();
+
+                // Notice: endpoints sharing an identity would share a queue, thus deliveries \
+                //   would get mixed up between them.
+                if identities.iter().collect::<HashSet<&String>>().len() != identities.len() {
+                    panic!("webhook endpoints must have distinct names, urls or secrets");
+                }
+
+                Self::drop_unconfigured_queues(db, &identities);
+
+                identities
+                    .iter()
+                    .map(|identity| match db.open_tree(Self::queue_name(identity)) {
+                        Ok(queue) => queue,
+                        Err(err) => panic!("could not open webhook spool queue: {}", err),
+                    })
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        WebhookSpool {
+            db: db,
+            queues: queues,
+        }
+    }
+
+    fn drop_unconfigured_queues(db: &Db, identities: &[String]) {
+        for queue_name in db.tree_names() {
+            let queue_identity = if queue_name.starts_with(WEBHOOK_QUEUE_PREFIX.as_bytes()) == true
+            {
+                str::from_utf8(&queue_name[WEBHOOK_QUEUE_PREFIX.len()..]).ok()
+            } else {
+                None
+            };
+
+            // Endpoint not configured anymore? Drop its pending deliveries.
+            if let Some(queue_identity) = queue_identity {
+                if identities.iter().any(|identity| identity == queue_identity) == false {
+                    warn!(
+                        "dropped webhook deliveries to unconfigured endpoint: {}",
+                        queue_identity
+                    );
+
+                    if let Err(err) = db.drop_tree(&queue_name) {
+                        error!("could not drop webhook spool queue: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    fn endpoint_identity(endpoint: &ConfigHTTPWebhookEndpoint) -> String {
+        match endpoint.name {
+            Some(ref name) => name.to_owned(),
+            None => format!(
+                "{:016x}",
+                farmhash::fingerprint64(
+                    format!("{}\n{}", endpoint.url.as_str(), endpoint.secret).as_bytes()
+                )
+            ),
+        }
+    }
+
+    fn queue_name(identity: &str) -> String {
+        format!("{}{}", WEBHOOK_QUEUE_PREFIX, identity)
+    }
+}
+
+impl WebhookSpool {
+    pub fn enqueue(&self, zone_name: &ZoneName, changes: &[StoreChange]) {
+        let db = match self.db {
+            Some(ref db) => db,
+            None => return,
+        };
+
+        let timestamp = Self::now();
+
+        for change in changes {
+            let event = match (&change.before, &change.after) {
+                (None, Some(_)) => ConfigHTTPWebhookEvent::Create,
+                (Some(_), Some(_)) => ConfigHTTPWebhookEvent::Update,
+                (Some(_), None) => ConfigHTTPWebhookEvent::Delete,
+                (None, None) => continue,
+            };
+
+            let payload = match serde_json::to_string(&WebhookPayload {
+                event: event,
+                timestamp: timestamp,
+                zone: zone_name,
+                name: &change.name,
+                kind: &change.kind,
+                old: change.before.as_ref().map(WebhookPayloadData::from),
+                new: change.after.as_ref().map(WebhookPayloadData::from),
+            }) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    error!("could not encode webhook payload: {}", err);
+
+                    continue;
+                }
+            };
+
+            for (endpoint, queue) in APP_CONF.http.webhook.endpoints.iter().zip(&self.queues) {
+                if Self::allows(endpoint, zone_name, event) == false {
+                    continue;
+                }
+
+                let delivery = WebhookDelivery {
+                    event: event,
+                    zone: zone_name.to_owned(),
+                    payload: payload.to_owned(),
+                    attempts: 0,
+                    attempt_at: timestamp,
+                };
+
+                if let Err(err) = Self::push(db, queue, &delivery) {
+                    error!("could not spool webhook delivery: {}", err);
+                }
+            }
+        }
+
+        // Notice: flush on each enqueue, so that deliveries survive a crash right after the \
+        //   API acknowledged the change they stand for.
+        if let Err(err) = db.flush() {
+            error!("could not flush webhook spool: {}", err);
+        }
+    }
+
+    fn dispatch(&self, endpoint_index: usize) {
+        let (endpoint, queue) = match (
+            APP_CONF.http.webhook.endpoints.get(endpoint_index),
+            self.queues.get(endpoint_index),
+        ) {
+            (Some(endpoint), Some(queue)) => (endpoint, queue),
+            _ => return,
+        };
+
+        let now = Self::now();
+
+        // Notice: deliveries are spooled in order, and a delivery is not sent until all previous \
+        //   deliveries to the same endpoint succeeded (or were given up on), so that an endpoint \
+        //   always receives events in the order they happened.
+        for entry in queue.iter() {
+            let (delivery_key, delivery_raw) = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    error!("could not read webhook spool: {}", err);
+
+                    break;
+                }
+            };
+
+            let mut delivery = match serde_json::from_slice::<WebhookDelivery>(&delivery_raw) {
+                Ok(delivery) => delivery,
+                Err(err) => {
+                    error!("dropped invalid webhook delivery from spool: {}", err);
+
+                    Self::pop(queue, &delivery_key);
+
+                    continue;
+                }
+            };
+
+            // Endpoint not notified of this event anymore? (configuration changed since spooled)
+            if Self::allows(endpoint, &delivery.zone, delivery.event) == false {
+                debug!(
+                    "dropped webhook delivery not allowed anymore to: {}",
+                    endpoint.url.as_str()
+                );
+
+                Self::pop(queue, &delivery_key);
+
+                continue;
+            }
+
+            if delivery.attempt_at > now {
+                break;
+            }
+
+            let delivery_id = delivery_key
+                .as_ref()
+                .try_into()
+                .map(u64::from_be_bytes)
+                .unwrap_or(0);
+
+            match WebhookDispatch::send(endpoint, &delivery, delivery_id) {
+                Ok(_) => {
+                    debug!("dispatched webhook delivery to: {}", endpoint.url.as_str());
+
+                    Self::pop(queue, &delivery_key);
+                }
+                Err(err) => {
+                    delivery.attempts += 1;
+
+                    if delivery.attempts >= APP_CONF.http.webhook.retry_attempts {
+                        error!(
+                            "gave up on webhook delivery to: {} after {} attempts: {}",
+                            endpoint.url.as_str(),
+                            delivery.attempts,
+                            err
+                        );
+
+                        Self::pop(queue, &delivery_key);
+                    } else {
+                        warn!(
+                            "webhook delivery to: {} failed (attempt {}), will retry: {}",
+                            endpoint.url.as_str(),
+                            delivery.attempts,
+                            err
+                        );
+
+                        delivery.attempt_at = now + Self::backoff(delivery.attempts);
+
+                        match serde_json::to_vec(&delivery) {
+                            Ok(delivery_raw) => {
+                                if let Err(err) = queue.insert(&delivery_key, delivery_raw) {
+                                    error!("could not update webhook delivery: {}", err);
+                                }
+                            }
+                            Err(err) => error!("could not encode webhook delivery: {}", err),
+                        }
+
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Err(err) = queue.flush() {
+            error!("could not flush webhook spool: {}", err);
+        }
+    }
+
+    fn allows(
+        endpoint: &ConfigHTTPWebhookEndpoint,
+        zone_name: &ZoneName,
+        event: ConfigHTTPWebhookEvent,
+    ) -> bool {
+        if endpoint.events.contains(&event) == false {
+            return false;
+        }
+
+        // Notice: endpoints with no zones configured are notified of changes in all zones
+        match endpoint.zones {
+            Some(ref zones) => zones.contains(zone_name),
+            None => true,
+        }
+    }
+
+    fn push(db: &Db, queue: &Tree, delivery: &WebhookDelivery) -> Result<(), String> {
+        // Notice: generated identifiers are monotonic, and are stored big-endian so that \
+        //   deliveries are iterated in the order they were spooled.
+        let delivery_id = db.generate_id().map_err(|err| err.to_string())?;
+        let delivery_raw = serde_json::to_vec(delivery).map_err(|err| err.to_string())?;
+
+        queue
+            .insert(&delivery_id.to_be_bytes(), delivery_raw)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    }
+
+    fn pop(queue: &Tree, delivery_key: &[u8]) {
+        if let Err(err) = queue.remove(delivery_key) {
+            error!("could not remove webhook delivery from spool: {}", err);
+        }
+    }
+
+    fn backoff(attempts: u32) -> u64 {
+        // Notice: the delay doubles on each failed attempt, up to a maximum delay
+        APP_CONF
+            .http
+            .webhook
+            .retry_backoff
+            .saturating_mul(2_u64.saturating_pow(attempts.saturating_sub(1)))
+            .min(APP_CONF.http.webhook.retry_backoff_maximum)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+impl WebhookDispatchBuilder {
+    pub fn new() -> WebhookDispatch {
+        WebhookDispatch {}
+    }
+}
+
+impl WebhookDispatch {
+    pub fn run(&self) {
+        // Notice: each endpoint is dispatched to from its own thread, so that a slow or \
+        //   unreachable endpoint does not delay deliveries to other endpoints.
+        let supervisors = (0..APP_CONF.http.webhook.endpoints.len())
+            .map(|endpoint_index| thread::spawn(move || Self::supervise(endpoint_index)))
+            .collect::<Vec<_>>();
+
+        info!("webhook dispatcher is now active");
+
+        for supervisor in supervisors {
+            let _ = supervisor.join();
+        }
+    }
+
+    fn supervise(endpoint_index: usize) {
+        loop {
+            let worker = thread::Builder::new()
+                .name(format!("{}-{}", THREAD_NAME_HTTP_WEBHOOK, endpoint_index))
+                .spawn(move || Self::run_endpoint(endpoint_index));
+
+            // Block on worker thread (join it)
+            if let Ok(worker_thread) = worker {
+                let _ = worker_thread.join();
+            }
+
+            // Worker thread crashed? (it never returns otherwise)
+            error!(
+                "webhook dispatcher crashed for endpoint: #{}, setting it up again",
+                endpoint_index
+            );
+
+            // Prevents thread start loop floods
+            thread::sleep(WEBHOOK_DISPATCH_RESPAWN_DELAY);
+        }
+    }
+
+    fn run_endpoint(endpoint_index: usize) {
+        debug!(
+            "webhook dispatcher is now active for endpoint: #{}",
+            endpoint_index
+        );
+
+        loop {
+            // Hold for next dispatch run
+            thread::sleep(WEBHOOK_DISPATCH_INTERVAL);
+
+            WEBHOOK_SPOOL.dispatch(endpoint_index);
+        }
+    }
+
+    fn send(
+        endpoint: &ConfigHTTPWebhookEndpoint,
+        delivery: &WebhookDelivery,
+        delivery_id: u64,
+    ) -> Result<(), String> {
+        let timestamp = WebhookSpool::now();
+        let signature = Self::sign(&endpoint.secret, timestamp, &delivery.payload)?;
+
+        let uri = Uri::from_str(endpoint.url.as_str()).map_err(|err| err.to_string())?;
+        let timeout = Duration::from_secs(APP_CONF.http.webhook.timeout);
+
+        let response = Request::new(&uri)
+            .connect_timeout(Some(timeout))
+            .read_timeout(Some(timeout))
+            .write_timeout(Some(timeout))
+            .method(Method::POST)
+            .header("User-Agent", WEBHOOK_USERAGENT)
+            .header("Content-Type", "application/json")
+            .header("Content-Length", &delivery.payload.len())
+            .header("X-Constellation-Event", delivery.event.to_str())
+            .header("X-Constellation-Delivery", &delivery_id)
+            .header("X-Constellation-Timestamp", &timestamp)
+            .header(
+                "X-Constellation-Signature",
+                &format!("sha256={}", signature),
+            )
+            .body(delivery.payload.as_bytes())
+            .send(&mut io::sink())
+            .map_err(|err| err.to_string())?;
+
+        if response.status_code().is_success() == true {
+            Ok(())
+        } else {
+            Err(format!("got invalid status: {}", response.status_code()))
+        }
+    }
+
+    fn sign(secret: &str, timestamp: u64, payload: &str) -> Result<String, String> {
+        // Notice: the timestamp is signed along with the payload, so that receivers can reject \
+        //   replayed requests.
+        let mut signature =
+            WebhookSignature::new_from_slice(secret.as_bytes()).map_err(|err| err.to_string())?;
+
+        signature.update(timestamp.to_string().as_bytes());
+        signature.update(b".");
+        signature.update(payload.as_bytes());
+
+        Ok(signature
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+}
+
+impl<'a> From<&'a StoreRecord> for WebhookPayloadData<'a> {
+    fn from(record: &'a StoreRecord) -> Self {
+        WebhookPayloadData {
+            ttl: record.ttl,
+            blackhole: &record.blackhole,
+            regions: &record.regions,
+            rescue: &record.rescue,
+            values: &record.values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_signs_timestamp_and_payload() {
+        assert_eq!(
+            WebhookDispatch::sign("secret", 1700000000, r#"{"event":"create"}"#),
+            Ok("4fb99875e3a0fb562c3b0cd271bbe4c9c9ac46f0847f122bffa183484be87e63".to_owned())
+        );
+
+        // Notice: the signature must change if the timestamp changes (replay protection)
+        assert!(
+            WebhookDispatch::sign("secret", 1700000001, r#"{"event":"create"}"#)
+                != WebhookDispatch::sign("secret", 1700000000, r#"{"event":"create"}"#)
+        );
+    }
+
+    fn endpoint_identity(endpoint_toml: &str) -> String {
+        WebhookSpoolBuilder::endpoint_identity(
+            &toml::from_str::<ConfigHTTPWebhookEndpoint>(endpoint_toml)
+                .expect("invalid test endpoint"),
+        )
+    }
+
+    #[test]
+    fn it_identifies_endpoints() {
+        assert_eq!(
+            endpoint_identity(
+                "name = \"provisioning\"\nurl = \"https://a.example.com/\"\nsecret = \"a\""
+            ),
+            "provisioning"
+        );
+
+        assert_eq!(
+            endpoint_identity("url = \"https://a.example.com/\"\nsecret = \"a\""),
+            endpoint_identity("url = \"https://a.example.com/\"\nsecret = \"a\"")
+        );
+
+        // Notice: the identity must change if either the URL or the secret changes
+        assert!(
+            endpoint_identity("url = \"https://a.example.com/\"\nsecret = \"a\"")
+                != endpoint_identity("url = \"https://b.example.com/\"\nsecret = \"a\"")
+        );
+        assert!(
+            endpoint_identity("url = \"https://a.example.com/\"\nsecret = \"a\"")
+                != endpoint_identity("url = \"https://a.example.com/\"\nsecret = \"b\"")
+        );
+    }
+
+    #[test]
+    fn it_backs_off_exponentially() {
+        assert_eq!(WebhookSpool::backoff(1), 10);
+        assert_eq!(WebhookSpool::backoff(2), 20);
+        assert_eq!(WebhookSpool::backoff(3), 40);
+    }
+
+    #[test]
+    fn it_caps_backoff_to_maximum() {
+        assert_eq!(WebhookSpool::backoff(10), 3600);
+        assert_eq!(WebhookSpool::backoff(u32::max_value()), 3600);
+    }
+}
@@ -16,22 +16,29 @@ extern crate lazy_static;
 extern crate serde_derive;
 #[macro_use]
 extern crate rocket;
+use argon2;
 use base64;
+use bcrypt;
 use farmhash;
 use flate2;
+use hmac;
 use http_req;
+use linked_hash_map;
 use maxminddb;
 use r2d2;
-use r2d2_redis;
 use rand;
 use redis;
 use regex;
 use rocket_contrib;
 use serde;
 use serde_json;
+use sha2;
+use sled;
+use subtle;
 use tar;
 use tempfile;
 use toml;
+use trust_dns_client;
 use trust_dns_proto;
 use trust_dns_server;
 use url_serde;
@@ -50,7 +57,7 @@ use std::time::Duration;
 use clap::{App, Arg};
 use log::LevelFilter;
 
-use config::config::Config;
+use config::config::{Config, ConfigStoreBackend};
 use config::logger::ConfigLogger;
 use config::reader::ConfigReader;
 use dns::health::DNSHealthBuilder;
@@ -59,7 +66,11 @@ use dns::metrics::DNSMetricsTickBuilder;
 use geo::locate::DB_READER;
 use geo::updater::GeoUpdaterBuilder;
 use http::listen::HTTPListenBuilder;
+use http::webhook::{WebhookDispatchBuilder, WEBHOOK_SPOOL};
 use store::flush::StoreFlushBuilder;
+use store::invalidate::StoreInvalidateBuilder;
+use store::sentinel::StoreSentinelBuilder;
+use store::warmup::StoreWarmupBuilder;
 use store::store::{Store, StoreBuilder};
 
 struct AppArgs {
@@ -68,7 +79,10 @@ struct AppArgs {
 
 pub static THREAD_NAME_DNS: &'static str = "constellation-dns";
 pub static THREAD_NAME_HTTP: &'static str = "constellation-http";
+pub static THREAD_NAME_HTTP_WEBHOOK: &'static str = "constellation-http-webhook";
 pub static THREAD_NAME_STORE_FLUSH: &'static str = "constellation-store-flush";
+pub static THREAD_NAME_STORE_INVALIDATE: &'static str = "constellation-store-invalidate";
+pub static THREAD_NAME_STORE_SENTINEL: &'static str = "constellation-store-sentinel";
 pub static THREAD_NAME_DNS_METRICS: &'static str = "constellation-dns-metrics";
 pub static THREAD_NAME_DNS_HEALTH: &'static str = "constellation-dns-health";
 pub static THREAD_NAME_GEO_UPDATER: &'static str = "constellation-geo-updater";
@@ -120,12 +134,30 @@ gen_spawn_managed!(
     THREAD_NAME_HTTP,
     HTTPListenBuilder::new().run()
 );
+gen_spawn_managed!(
+    "http_webhook",
+    spawn_http_webhook,
+    THREAD_NAME_HTTP_WEBHOOK,
+    WebhookDispatchBuilder::new().run()
+);
 gen_spawn_managed!(
     "store_flush",
     spawn_store_flush,
     THREAD_NAME_STORE_FLUSH,
     StoreFlushBuilder::new().run()
 );
+gen_spawn_managed!(
+    "store_invalidate",
+    spawn_store_invalidate,
+    THREAD_NAME_STORE_INVALIDATE,
+    StoreInvalidateBuilder::new().run()
+);
+gen_spawn_managed!(
+    "store_sentinel",
+    spawn_store_sentinel,
+    THREAD_NAME_STORE_SENTINEL,
+    StoreSentinelBuilder::new().run()
+);
 gen_spawn_managed!(
     "dns_metrics",
     spawn_dns_metrics,
@@ -145,6 +177,7 @@ gen_spawn_managed!(
     GeoUpdaterBuilder::new().run()
 );
 
+#[cfg(not(test))]
 fn make_app_args() -> AppArgs {
     let matches = App::new(crate_name!())
         .version(crate_version!())
@@ -166,13 +199,22 @@ fn make_app_args() -> AppArgs {
     }
 }
 
+#[cfg(test)]
+fn make_app_args() -> AppArgs {
+    // Tests run against a dedicated configuration (in-memory store and test geo database)
+    AppArgs {
+        config: String::from("./res/tests/config.cfg"),
+    }
+}
+
 fn ensure_states() {
     // Ensure all statics are valid (a `deref` is enough to lazily initialize them)
-    let (_, _, _, _) = (
+    let (_, _, _, _, _) = (
         APP_ARGS.deref(),
         APP_CONF.deref(),
         APP_STORE.deref(),
         DB_READER.deref(),
+        WEBHOOK_SPOOL.deref(),
     );
 }
 
@@ -186,8 +228,26 @@ fn main() {
     // Ensure all states are bound
     ensure_states();
 
-    // Spawn store flush
-    thread::spawn(spawn_store_flush);
+    // Bind Redis-specific store tasks? (only the Redis backend uses a local cache)
+    if APP_CONF.store.backend == ConfigStoreBackend::Redis {
+        // Warm up store cache? (blocking, before DNS queries are accepted)
+        if APP_CONF.redis.warmup_enable == true {
+            StoreWarmupBuilder::new().run();
+        }
+
+        // Spawn store flush
+        thread::spawn(spawn_store_flush);
+
+        // Spawn store invalidation subscriber? (background thread)
+        if APP_CONF.redis.invalidate_enable == true {
+            thread::spawn(spawn_store_invalidate);
+        }
+
+        // Spawn store sentinel watcher? (background thread)
+        if APP_CONF.redis.sentinel.is_some() == true {
+            thread::spawn(spawn_store_sentinel);
+        }
+    }
 
     // Spawn DNS metrics
     thread::spawn(spawn_dns_metrics);
@@ -202,6 +262,11 @@ fn main() {
         thread::spawn(spawn_geo_updater);
     }
 
+    // Spawn HTTP webhook dispatcher? (background thread)
+    if APP_CONF.http.webhook.endpoints.is_empty() == false {
+        thread::spawn(spawn_http_webhook);
+    }
+
     // Spawn HTTP server (background thread)
     thread::spawn(spawn_http);
 
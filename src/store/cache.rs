@@ -4,9 +4,11 @@
 // Copyright: 2019, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use std::collections::HashMap;
+use farmhash;
+use linked_hash_map::LinkedHashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::RwLock;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use super::store::StoreRecord;
 use crate::APP_CONF;
@@ -16,59 +18,128 @@ lazy_static! {
     pub static ref STORE_CACHE: StoreCache = StoreCacheBuilder::new();
 }
 
+const CACHE_SHARDS: usize = 16;
+
 struct StoreCacheBuilder;
 
 pub struct StoreCache {
-    cache: RwLock<HashMap<String, StoreCacheEntry>>,
+    shards: Vec<RwLock<StoreCacheShard>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
 }
 
 pub struct StoreCacheFlush;
 
+#[derive(Serialize)]
+pub struct StoreCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub hit_ratio: f64,
+    pub size: usize,
+    pub size_negative: usize,
+    pub size_stale: usize,
+}
+
+// Notice: entries are ordered from the least to the most recently inserted, and negative entries \
+//   are also ordered separately, so that evicting either kind of entry is done in constant time. \
+//   As reads only hold a read lock, they do not reorder entries, but flag them as referenced, \
+//   which is accounted for upon eviction.
+#[derive(Default)]
+struct StoreCacheShard {
+    entries: LinkedHashMap<String, StoreCacheEntry>,
+    negative: LinkedHashMap<String, ()>,
+}
+
 struct StoreCacheEntry {
     record: Option<StoreRecord>,
     refreshed_at: SystemTime,
-    accessed_at: SystemTime,
+    accessed_at: AtomicU64,
+    referenced: AtomicBool,
+    stale_at: Option<SystemTime>,
 }
 
 impl StoreCacheBuilder {
     fn new() -> StoreCache {
         StoreCache {
-            cache: RwLock::new(HashMap::new()),
+            shards: (0..CACHE_SHARDS)
+                .map(|_| RwLock::new(StoreCacheShard::default()))
+                .collect(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
         }
     }
 }
 
 impl StoreCache {
     pub fn has(&self, store_key: &str) -> bool {
-        let mut cache_write = self.cache.write().unwrap();
+        let shard_read = self.shard(store_key).read().unwrap();
 
         debug!("store cache has on key: {}", store_key);
 
-        if let Some(ref mut store_record) = cache_write.get_mut(store_key) {
-            // Bump last cache access time
-            store_record.accessed_at = SystemTime::now();
+        if let Some(store_record) = shard_read.access(store_key) {
+            // Stale records are not considered (they are only served if the store is down)
+            if store_record.stale_at.is_some() == true {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+
+                return false;
+            }
+
+            self.hits.fetch_add(1, Ordering::Relaxed);
 
             store_record.record.is_some()
         } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+
             false
         }
     }
 
     pub fn get(&self, store_key: &str) -> Result<Option<StoreRecord>, ()> {
-        let mut cache_write = self.cache.write().unwrap();
+        let shard_read = self.shard(store_key).read().unwrap();
 
         debug!("store cache get on key: {}", store_key);
 
-        if let Some(ref mut store_record) = cache_write.get_mut(store_key) {
+        if let Some(store_record) = shard_read.access(store_key) {
+            // Stale records are not considered (they are only served if the store is down)
+            if store_record.stale_at.is_some() == true {
+                debug!("store cache get got stale records for key: {}", store_key);
+
+                self.misses.fetch_add(1, Ordering::Relaxed);
+
+                return Err(());
+            }
+
             debug!("store cache get got records for key: {}", store_key);
 
-            // Bump last cache access time
-            store_record.accessed_at = SystemTime::now();
+            self.hits.fetch_add(1, Ordering::Relaxed);
 
             Ok(store_record.record.clone())
         } else {
             debug!("store cache get did not get records for key: {}", store_key);
 
+            self.misses.fetch_add(1, Ordering::Relaxed);
+
+            Err(())
+        }
+    }
+
+    pub fn get_stale(&self, store_key: &str) -> Result<Option<StoreRecord>, ()> {
+        let shard_read = self.shard(store_key).read().unwrap();
+
+        debug!("store cache get stale on key: {}", store_key);
+
+        // Notice: this returns both stale and non-stale records, as it is used as a last resort \
+        //   when the store is down.
+        if let Some(store_record) = shard_read.entries.get(store_key) {
+            debug!("store cache get stale got records for key: {}", store_key);
+
+            Ok(store_record.record.clone())
+        } else {
+            debug!("store cache get stale did not get records for key: {}", store_key);
+
             Err(())
         }
     }
@@ -79,60 +150,180 @@ impl StoreCache {
         store_record: Option<StoreRecord>,
         accessed_at: Option<SystemTime>,
     ) {
-        let mut cache_write = self.cache.write().unwrap();
+        let mut shard_write = self.shard(store_key).write().unwrap();
 
         debug!("store cache push on key: {}", store_key);
 
-        cache_write.insert(
-            store_key.to_string(),
+        // Notice: entries pushed with a restored access time (ie. upon refresh) keep their \
+        //   position, as refreshing an entry does not count as an access.
+        shard_write.insert(
+            store_key,
             StoreCacheEntry::new(store_record, accessed_at),
+            accessed_at.is_none(),
         );
+
+        // Enforce size limits (evict least recently used entries)
+        let (max_entries, max_negative_entries) = (
+            Self::limit_per_shard(APP_CONF.redis.cache_max_entries),
+            Self::limit_per_shard(APP_CONF.redis.cache_max_negative_entries),
+        );
+
+        while shard_write.negative.len() > max_negative_entries {
+            self.evict_one(&mut shard_write, true);
+        }
+
+        while shard_write.entries.len() > max_entries {
+            self.evict_one(&mut shard_write, false);
+        }
     }
 
     pub fn pop(&self, store_key: &str) {
-        let mut cache_write = self.cache.write().unwrap();
+        let mut shard_write = self.shard(store_key).write().unwrap();
 
         debug!("store cache pop on key: {}", store_key);
 
-        cache_write.remove(store_key);
+        shard_write.remove(store_key);
+    }
+
+    pub fn mark_refresh_all(&self) {
+        debug!("store cache mark all keys for refresh");
+
+        // Notice: this makes all entries due for refresh upon next store flush
+        for shard in &self.shards {
+            let mut shard_write = shard.write().unwrap();
+
+            for (_, entry) in shard_write.entries.iter_mut() {
+                entry.refreshed_at = UNIX_EPOCH;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> StoreCacheStats {
+        let (hits, misses, evictions) = (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.evictions.load(Ordering::Relaxed),
+        );
+
+        let (mut size, mut size_negative, mut size_stale) = (0, 0, 0);
+
+        for shard in &self.shards {
+            let shard_read = shard.read().unwrap();
+
+            size += shard_read.entries.len();
+            size_negative += shard_read.negative.len();
+            size_stale += shard_read
+                .entries
+                .values()
+                .filter(|entry| entry.stale_at.is_some())
+                .count();
+        }
+
+        StoreCacheStats {
+            hits: hits,
+            misses: misses,
+            evictions: evictions,
+            hit_ratio: if hits + misses > 0 {
+                hits as f64 / (hits + misses) as f64
+            } else {
+                0.0
+            },
+            size: size,
+            size_negative: size_negative,
+            size_stale: size_stale,
+        }
+    }
+
+    fn shard(&self, store_key: &str) -> &RwLock<StoreCacheShard> {
+        &self.shards[farmhash::hash32(store_key.as_bytes()) as usize % CACHE_SHARDS]
+    }
+
+    fn limit_per_shard(limit: usize) -> usize {
+        // Notice: round up, so that a low limit does not result in an always-empty cache
+        (limit + CACHE_SHARDS - 1) / CACHE_SHARDS
+    }
+
+    fn evict_one(&self, shard: &mut StoreCacheShard, negative_only: bool) {
+        // Evict least recently used entry
+        if let Some(evict_key) = shard.pop_least_recent(negative_only) {
+            debug!("store cache evict on key: {}", evict_key);
+
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
     }
 }
 
 impl StoreCacheFlush {
     pub fn expire() {
+        Self::expire_cache(&STORE_CACHE)
+    }
+
+    fn expire_cache(cache: &StoreCache) {
         debug!("flushing expired store cache records");
 
-        let mut expire_register: Vec<String> = Vec::new();
+        let (mut count_expire, mut count_stale) = (0, 0);
+
+        for shard in &cache.shards {
+            let mut expire_register: Vec<String> = Vec::new();
+            let mut stale_register: Vec<String> = Vec::new();
+
+            // Scan for expired store items
+            {
+                let shard_read = shard.read().unwrap();
+                let now_time = SystemTime::now();
+
+                for (store_key, store) in shard_read.entries.iter() {
+                    if let Some(stale_at) = store.stale_at {
+                        // Stale item kept for long enough? Expire it for good.
+                        let stale_elapsed = now_time
+                            .duration_since(stale_at)
+                            .unwrap_or_default()
+                            .as_secs();
+
+                        if stale_elapsed >= APP_CONF.redis.stale_max_seconds {
+                            expire_register.push(store_key.to_owned());
+                        }
+                    } else {
+                        let store_elapsed = now_time
+                            .duration_since(store.accessed_at())
+                            .unwrap_or(Duration::from_secs(0))
+                            .as_secs();
+
+                        if store_elapsed >= APP_CONF.redis.cache_expire_seconds {
+                            // Keep item as stale? (it may be served if the store goes down)
+                            if APP_CONF.redis.stale_max_seconds > 0 {
+                                stale_register.push(store_key.to_owned());
+                            } else {
+                                expire_register.push(store_key.to_owned());
+                            }
+                        }
+                    }
+                }
+            }
 
-        // Scan for expired store items
-        {
-            let cache_read = STORE_CACHE.cache.read().unwrap();
-            let now_time = SystemTime::now();
+            // Any store item to expire or mark as stale?
+            if expire_register.is_empty() == false || stale_register.is_empty() == false {
+                let mut shard_write = shard.write().unwrap();
+                let now_time = SystemTime::now();
 
-            for (store_key, store) in cache_read.iter() {
-                let store_elapsed = now_time
-                    .duration_since(store.accessed_at)
-                    .unwrap()
-                    .as_secs();
+                for store_key in &expire_register {
+                    shard_write.remove(store_key);
+                }
 
-                if store_elapsed >= APP_CONF.redis.cache_expire_seconds {
-                    expire_register.push(store_key.to_owned());
+                for store_key in &stale_register {
+                    if let Some(ref mut store_record) = shard_write.entries.get_mut(store_key) {
+                        store_record.stale_at = Some(now_time);
+                    }
                 }
             }
-        }
 
-        // Any store item to expire?
-        if expire_register.is_empty() == false {
-            let mut cache_write = STORE_CACHE.cache.write().unwrap();
-
-            for store_key in &expire_register {
-                cache_write.remove(store_key);
-            }
+            count_expire += expire_register.len();
+            count_stale += stale_register.len();
         }
 
         debug!(
-            "flushed expired store cache records (count: {})",
-            expire_register.len()
+            "flushed expired store cache records (count: {}, stale: {})",
+            count_expire, count_stale
         );
     }
 
@@ -142,28 +333,33 @@ impl StoreCacheFlush {
         let mut refresh_register: Vec<(String, SystemTime)> = Vec::new();
 
         // Scan for to-be-refreshed store items
-        {
-            let cache_read = STORE_CACHE.cache.read().unwrap();
+        for shard in &STORE_CACHE.shards {
+            let shard_read = shard.read().unwrap();
             let now_time = SystemTime::now();
 
-            for (store_key, store) in cache_read.iter() {
+            for (store_key, store) in shard_read.entries.iter() {
+                // Stale items are not refreshed (they are not being accessed anymore)
+                if store.stale_at.is_some() == true {
+                    continue;
+                }
+
                 let store_elapsed = now_time
                     .duration_since(store.refreshed_at)
-                    .unwrap()
+                    .unwrap_or_default()
                     .as_secs();
 
                 if store_elapsed >= APP_CONF.redis.cache_refresh_seconds {
-                    refresh_register.push((store_key.to_owned(), store.accessed_at));
+                    refresh_register.push((store_key.to_owned(), store.accessed_at()));
                 }
             }
         }
 
         // Any store item to refresh?
-        if refresh_register.is_empty() == false {
+        if let (false, Some(remote)) = (refresh_register.is_empty(), APP_STORE.remote()) {
             for (store_key, store_accessed_at) in &refresh_register {
                 // Notice: restore 'accessed_at' time, otherwise a never-accessed cache entry \
                 //   would never be expired.
-                APP_STORE
+                remote
                     .raw_get_remote(store_key, Some(*store_accessed_at))
                     .ok();
             }
@@ -176,6 +372,76 @@ impl StoreCacheFlush {
     }
 }
 
+impl StoreCacheShard {
+    fn access(&self, store_key: &str) -> Option<&StoreCacheEntry> {
+        let entry = self.entries.get(store_key)?;
+
+        // Notice: stale entries are not bumped, as they are not served from the cache
+        if entry.stale_at.is_none() == true {
+            entry.touch();
+        }
+
+        Some(entry)
+    }
+
+    fn insert(&mut self, store_key: &str, entry: StoreCacheEntry, bump: bool) {
+        let is_negative = entry.record.is_none();
+
+        if bump == false && self.entries.contains_key(store_key) == true {
+            if let Some(existing_entry) = self.entries.get_mut(store_key) {
+                entry.referenced.store(
+                    existing_entry.referenced.load(Ordering::Relaxed),
+                    Ordering::Relaxed,
+                );
+
+                *existing_entry = entry;
+            }
+        } else {
+            self.entries.insert(store_key.to_owned(), entry);
+        }
+
+        if is_negative == false {
+            self.negative.remove(store_key);
+        } else if bump == true || self.negative.contains_key(store_key) == false {
+            self.negative.insert(store_key.to_owned(), ());
+        }
+    }
+
+    fn remove(&mut self, store_key: &str) -> Option<StoreCacheEntry> {
+        self.negative.remove(store_key);
+
+        self.entries.remove(store_key)
+    }
+
+    fn pop_least_recent(&mut self, negative_only: bool) -> Option<String> {
+        // Notice: entries referenced since they were last considered for eviction are given a \
+        //   second chance, and moved to the most recently used position instead (this \
+        //   terminates, as giving a second chance clears the referenced flag).
+        loop {
+            let store_key = if negative_only == true {
+                self.negative.front()?.0.to_owned()
+            } else {
+                self.entries.front()?.0.to_owned()
+            };
+
+            let referenced = self
+                .entries
+                .get(&store_key)
+                .map(|entry| entry.referenced.swap(false, Ordering::Relaxed))
+                .unwrap_or(false);
+
+            if referenced == true {
+                self.entries.get_refresh(&store_key);
+                self.negative.get_refresh(&store_key);
+            } else {
+                self.remove(&store_key);
+
+                return Some(store_key);
+            }
+        }
+    }
+}
+
 impl StoreCacheEntry {
     fn new(record: Option<StoreRecord>, accessed_at: Option<SystemTime>) -> StoreCacheEntry {
         let time_now = SystemTime::now();
@@ -183,7 +449,184 @@ impl StoreCacheEntry {
         StoreCacheEntry {
             record: record,
             refreshed_at: time_now,
-            accessed_at: accessed_at.unwrap_or(time_now),
+            accessed_at: AtomicU64::new(Self::to_millis(accessed_at.unwrap_or(time_now))),
+            referenced: AtomicBool::new(false),
+            stale_at: None,
+        }
+    }
+
+    fn touch(&self) {
+        // Notice: access time and referenced flag are stored as atomics, so that they can be \
+        //   bumped from a read lock
+        self.accessed_at
+            .store(Self::to_millis(SystemTime::now()), Ordering::Relaxed);
+        self.referenced.store(true, Ordering::Relaxed);
+    }
+
+    fn accessed_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.accessed_at.load(Ordering::Relaxed))
+    }
+
+    fn to_millis(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn make_record() -> StoreRecord {
+        serde_json::from_str::<StoreRecord>(
+            r#"{"kind":"a","name":"cache.@","values":["10.0.0.1"]}"#,
+        )
+        .expect("invalid test record")
+    }
+
+    fn make_shard(entries: &[(&str, bool)]) -> StoreCacheShard {
+        let mut shard = StoreCacheShard::default();
+
+        for (store_key, is_negative) in entries {
+            let record = if *is_negative == true {
+                None
+            } else {
+                Some(make_record())
+            };
+
+            shard.insert(store_key, StoreCacheEntry::new(record, None), true);
         }
+
+        shard
+    }
+
+    #[test]
+    fn it_evicts_least_recently_used_entries() {
+        let mut shard = make_shard(&[("a", false), ("b", false), ("c", false)]);
+
+        assert!(shard.access("a").is_some());
+
+        assert_eq!(shard.pop_least_recent(false), Some("b".to_owned()));
+        assert_eq!(shard.pop_least_recent(false), Some("c".to_owned()));
+        assert_eq!(shard.pop_least_recent(false), Some("a".to_owned()));
+        assert_eq!(shard.pop_least_recent(false), None);
+    }
+
+    #[test]
+    fn it_evicts_when_all_entries_are_referenced() {
+        let mut shard = make_shard(&[("a", false), ("b", false)]);
+
+        assert!(shard.access("a").is_some());
+        assert!(shard.access("b").is_some());
+
+        assert_eq!(shard.pop_least_recent(false), Some("a".to_owned()));
+        assert_eq!(shard.pop_least_recent(false), Some("b".to_owned()));
+    }
+
+    #[test]
+    fn it_evicts_negative_entries_separately() {
+        let mut shard = make_shard(&[("a", true), ("b", false), ("c", true)]);
+
+        assert!(shard.access("a").is_some());
+
+        assert_eq!(shard.pop_least_recent(true), Some("c".to_owned()));
+        assert_eq!(shard.negative.len(), 1);
+        assert_eq!(shard.entries.len(), 2);
+
+        assert_eq!(shard.pop_least_recent(false), Some("b".to_owned()));
+        assert_eq!(shard.pop_least_recent(false), Some("a".to_owned()));
+        assert!(shard.negative.is_empty());
+    }
+
+    #[test]
+    fn it_tracks_negative_entries_on_replace() {
+        let mut shard = make_shard(&[("a", true), ("b", true)]);
+
+        shard.insert("a", StoreCacheEntry::new(None, None), true);
+
+        assert_eq!(shard.negative.len(), 2);
+        assert_eq!(shard.pop_least_recent(true), Some("b".to_owned()));
+
+        shard.insert("a", StoreCacheEntry::new(Some(make_record()), None), true);
+
+        assert!(shard.negative.is_empty());
+        assert_eq!(shard.entries.len(), 1);
+        assert!(shard.remove("a").is_some());
+        assert!(shard.entries.is_empty());
+    }
+
+    #[test]
+    fn it_keeps_position_on_refresh() {
+        let mut shard = make_shard(&[("a", false), ("b", false)]);
+
+        shard.insert(
+            "a",
+            StoreCacheEntry::new(None, Some(SystemTime::now())),
+            false,
+        );
+
+        assert_eq!(shard.negative.len(), 1);
+        assert_eq!(shard.pop_least_recent(false), Some("a".to_owned()));
+        assert!(shard.negative.is_empty());
+    }
+
+    #[test]
+    fn it_does_not_bump_stale_entries() {
+        let mut shard = make_shard(&[("a", false), ("b", false)]);
+
+        if let Some(entry) = shard.entries.get_mut("a") {
+            entry.stale_at = Some(SystemTime::now());
+        }
+
+        assert!(shard.access("a").is_some());
+        assert_eq!(shard.pop_least_recent(false), Some("a".to_owned()));
+    }
+
+    #[test]
+    fn it_rounds_up_limit_per_shard() {
+        assert_eq!(StoreCache::limit_per_shard(0), 0);
+        assert_eq!(StoreCache::limit_per_shard(1), 1);
+        assert_eq!(StoreCache::limit_per_shard(CACHE_SHARDS), 1);
+        assert_eq!(StoreCache::limit_per_shard(CACHE_SHARDS * 2 + 1), 3);
+    }
+
+    #[test]
+    fn it_serves_stale_entries_as_last_resort() {
+        let cache = StoreCacheBuilder::new();
+
+        cache.push("cache:stale", Some(make_record()), None);
+
+        assert!(cache.has("cache:stale"));
+
+        for shard in &cache.shards {
+            if let Some(entry) = shard.write().unwrap().entries.get_mut("cache:stale") {
+                entry.stale_at = Some(SystemTime::now());
+            }
+        }
+
+        assert!(cache.has("cache:stale") == false);
+        assert!(cache.get("cache:stale").is_err());
+        assert!(cache.get_stale("cache:stale").unwrap().is_some());
+        assert!(cache.get_stale("cache:missing").is_err());
+    }
+
+    #[test]
+    fn it_keeps_stale_entries_on_clock_skew() {
+        let cache = StoreCacheBuilder::new();
+
+        cache.push("cache:stale-skew", Some(make_record()), None);
+
+        // Notice: a stale time in the future happens if the system clock goes backwards
+        for shard in &cache.shards {
+            if let Some(entry) = shard.write().unwrap().entries.get_mut("cache:stale-skew") {
+                entry.stale_at = Some(SystemTime::now() + Duration::from_secs(3600));
+            }
+        }
+
+        StoreCacheFlush::expire_cache(&cache);
+
+        assert!(cache.get_stale("cache:stale-skew").is_ok());
     }
 }
@@ -0,0 +1,262 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use r2d2::ManageConnection;
+use redis::cluster::{ClusterClient, ClusterClientBuilder, ClusterConnection};
+use redis::{
+    self, Client, Connection, ConnectionAddr, ConnectionInfo, ConnectionLike, IntoConnectionInfo,
+    RedisConnectionInfo, RedisError, RedisResult, TlsCertificates, TlsMode, Value,
+};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::config::{ConfigRedisMaster, ConfigRedisRescue};
+use crate::APP_CONF;
+
+pub struct StoreConnectionTarget {
+    pub url: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub socket_path: Option<PathBuf>,
+    pub tls: bool,
+    pub ca_file: Option<PathBuf>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+pub enum StoreConnectionManager {
+    Node(Client),
+    Cluster(ClusterClient),
+}
+
+pub enum StoreConnection {
+    Node(Connection),
+    Cluster(ClusterConnection),
+}
+
+impl StoreConnectionTarget {
+    pub fn with_address(&self, host: &str, port: u16) -> StoreConnectionTarget {
+        // Notice: used for discovered targets (eg. from Sentinel), which only inherit \
+        //   security options (the URL and socket path cannot apply there).
+        StoreConnectionTarget {
+            url: None,
+            host: host.to_owned(),
+            port: port,
+            socket_path: None,
+            tls: self.tls,
+            ca_file: self.ca_file.to_owned(),
+            username: self.username.to_owned(),
+            password: self.password.to_owned(),
+        }
+    }
+
+    pub fn client(&self) -> RedisResult<Client> {
+        let connection_info = self.connection_info()?;
+
+        match self.certificates()? {
+            Some(certificates) => Client::build_with_tls(connection_info, certificates),
+            None => Client::open(connection_info),
+        }
+    }
+
+    fn connection_info(&self) -> RedisResult<ConnectionInfo> {
+        // Full connection URL takes precedence over explicit options
+        if let Some(ref url) = self.url {
+            return url.as_str().into_connection_info();
+        }
+
+        let addr = if let Some(ref socket_path) = self.socket_path {
+            ConnectionAddr::Unix(socket_path.to_owned())
+        } else if self.tls == true {
+            ConnectionAddr::TcpTls {
+                host: self.host.to_owned(),
+                port: self.port,
+                insecure: false,
+                tls_params: None,
+            }
+        } else {
+            ConnectionAddr::Tcp(self.host.to_owned(), self.port)
+        };
+
+        Ok(ConnectionInfo {
+            addr: addr,
+            redis: RedisConnectionInfo {
+                db: APP_CONF.redis.database as i64,
+                username: self.username.to_owned(),
+                password: self.password.to_owned(),
+            },
+        })
+    }
+
+    fn certificates(&self) -> RedisResult<Option<TlsCertificates>> {
+        match self.ca_file {
+            Some(ref ca_file) => Ok(Some(TlsCertificates {
+                client_tls: None,
+                root_cert: Some(fs::read(ca_file)?),
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+impl From<&ConfigRedisMaster> for StoreConnectionTarget {
+    fn from(master: &ConfigRedisMaster) -> Self {
+        StoreConnectionTarget {
+            url: master.url.to_owned(),
+            host: master.host.to_owned(),
+            port: master.port,
+            socket_path: master.socket_path.to_owned(),
+            tls: master.tls,
+            ca_file: master.ca_file.to_owned(),
+            username: master.username.to_owned(),
+            password: master.password.to_owned(),
+        }
+    }
+}
+
+impl From<&ConfigRedisRescue> for StoreConnectionTarget {
+    fn from(rescue: &ConfigRedisRescue) -> Self {
+        StoreConnectionTarget {
+            url: rescue.url.to_owned(),
+            host: rescue.host.to_owned(),
+            port: rescue.port,
+            socket_path: rescue.socket_path.to_owned(),
+            tls: rescue.tls,
+            ca_file: rescue.ca_file.to_owned(),
+            username: rescue.username.to_owned(),
+            password: rescue.password.to_owned(),
+        }
+    }
+}
+
+impl StoreConnectionManager {
+    pub fn node(target: &StoreConnectionTarget) -> RedisResult<StoreConnectionManager> {
+        Ok(StoreConnectionManager::Node(target.client()?))
+    }
+
+    pub fn cluster(
+        nodes: &[String],
+        target: &StoreConnectionTarget,
+    ) -> RedisResult<StoreConnectionManager> {
+        let scheme = if target.tls == true {
+            "rediss"
+        } else {
+            "redis"
+        };
+
+        let mut builder = ClusterClientBuilder::new(
+            nodes
+                .iter()
+                .map(|node| format!("{}://{}/", scheme, node))
+                .collect::<Vec<String>>(),
+        );
+
+        if let Some(ref username) = target.username {
+            builder = builder.username(username.to_owned());
+        }
+        if let Some(ref password) = target.password {
+            builder = builder.password(password.to_owned());
+        }
+
+        if target.tls == true {
+            builder = builder.tls(TlsMode::Secure);
+        }
+        if let Some(certificates) = target.certificates()? {
+            builder = builder.certs(certificates);
+        }
+
+        Ok(StoreConnectionManager::Cluster(builder.build()?))
+    }
+}
+
+impl ManageConnection for StoreConnectionManager {
+    type Connection = StoreConnection;
+    type Error = RedisError;
+
+    fn connect(&self) -> Result<StoreConnection, RedisError> {
+        match self {
+            StoreConnectionManager::Node(client) => {
+                client.get_connection().map(StoreConnection::Node)
+            }
+            StoreConnectionManager::Cluster(client) => {
+                client.get_connection().map(StoreConnection::Cluster)
+            }
+        }
+    }
+
+    fn is_valid(&self, connection: &mut StoreConnection) -> Result<(), RedisError> {
+        match connection {
+            StoreConnection::Node(connection) => redis::cmd("PING").query(connection),
+            StoreConnection::Cluster(connection) => {
+                // Notice: a cluster connection holds one connection per node, that all need to \
+                //   be checked (a plain 'PING' would only hit a random node).
+                if connection.check_connection() == true {
+                    Ok(())
+                } else {
+                    Err(RedisError::from((
+                        redis::ErrorKind::IoError,
+                        "cluster connection is broken",
+                    )))
+                }
+            }
+        }
+    }
+
+    fn has_broken(&self, connection: &mut StoreConnection) -> bool {
+        connection.is_open() == false
+    }
+}
+
+impl ConnectionLike for StoreConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        match self {
+            StoreConnection::Node(connection) => connection.req_packed_command(cmd),
+            StoreConnection::Cluster(connection) => connection.req_packed_command(cmd),
+        }
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        match self {
+            StoreConnection::Node(connection) => connection.req_packed_commands(cmd, offset, count),
+            StoreConnection::Cluster(connection) => {
+                connection.req_packed_commands(cmd, offset, count)
+            }
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        match self {
+            StoreConnection::Node(connection) => connection.get_db(),
+            StoreConnection::Cluster(connection) => connection.get_db(),
+        }
+    }
+
+    fn supports_pipelining(&self) -> bool {
+        match self {
+            StoreConnection::Node(connection) => connection.supports_pipelining(),
+            StoreConnection::Cluster(connection) => connection.supports_pipelining(),
+        }
+    }
+
+    fn check_connection(&mut self) -> bool {
+        match self {
+            StoreConnection::Node(connection) => connection.check_connection(),
+            StoreConnection::Cluster(connection) => connection.check_connection(),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        match self {
+            StoreConnection::Node(connection) => connection.is_open(),
+            StoreConnection::Cluster(connection) => connection.is_open(),
+        }
+    }
+}
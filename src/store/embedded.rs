@@ -0,0 +1,274 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use serde_json;
+use sled::transaction::{abort, TransactionError};
+use sled::{self, Db};
+
+use super::key::StoreKey;
+use super::store::{
+    RecordStore, StoreActor, StoreBatchOperation, StoreChange, StoreCondition,
+    StoreConditionExpect, StoreError, StoreIndexEntry, StoreRecord, StoreRevision,
+};
+use crate::dns::record::{RecordName, RecordType};
+use crate::dns::zone::ZoneName;
+
+use crate::APP_CONF;
+
+pub struct StoreEmbeddedBuilder;
+
+pub struct StoreEmbedded {
+    db: Db,
+}
+
+struct StoreEmbeddedWrite {
+    name: RecordName,
+    kind: RecordType,
+    store_key: String,
+    history_key: String,
+    record: Option<(StoreRecord, Vec<u8>)>,
+}
+
+impl StoreEmbeddedBuilder {
+    pub fn new() -> StoreEmbedded {
+        info!(
+            "opening embedded store at: {:?}",
+            APP_CONF.store.embedded.path
+        );
+
+        match sled::open(&APP_CONF.store.embedded.path) {
+            Ok(db) => {
+                info!("opened embedded store");
+
+                StoreEmbedded { db: db }
+            }
+            Err(err) => panic!("could not open embedded store: {}", err),
+        }
+    }
+}
+
+impl RecordStore for StoreEmbedded {
+    fn check(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<(), StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        match self.db.contains_key(&store_key) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(StoreError::NotFound),
+            Err(err) => Err(StoreError::Embedded(err)),
+        }
+    }
+
+    fn get(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        match self.db.get(&store_key) {
+            Ok(Some(record_raw)) => {
+                serde_json::from_slice(&record_raw).or(Err(StoreError::Corrupted))
+            }
+            Ok(None) => Err(StoreError::NotFound),
+            Err(err) => Err(StoreError::Embedded(err)),
+        }
+    }
+
+    fn batch(
+        &self,
+        zone_name: &ZoneName,
+        operations: Vec<StoreBatchOperation>,
+        conditions: Vec<StoreCondition>,
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError> {
+        // Encode all records first (the transaction closure may run multiple times)
+        let mut writes = Vec::new();
+
+        for operation in operations {
+            match operation {
+                StoreBatchOperation::Set(mut record) => {
+                    record.renew_version();
+
+                    let record_raw =
+                        serde_json::to_vec(&record).map_err(|err| StoreError::Encoding(err))?;
+
+                    writes.push(StoreEmbeddedWrite {
+                        name: record.name.to_owned(),
+                        kind: record.kind.to_owned(),
+                        store_key: StoreKey::to_key(zone_name, &record.name, &record.kind),
+                        history_key: StoreKey::to_history_key(
+                            zone_name,
+                            &record.name,
+                            &record.kind,
+                        ),
+                        record: Some((record, record_raw)),
+                    });
+                }
+                StoreBatchOperation::Remove(record_name, record_type) => {
+                    writes.push(StoreEmbeddedWrite {
+                        store_key: StoreKey::to_key(zone_name, &record_name, &record_type),
+                        history_key: StoreKey::to_history_key(
+                            zone_name,
+                            &record_name,
+                            &record_type,
+                        ),
+                        name: record_name,
+                        kind: record_type,
+                        record: None,
+                    });
+                }
+            }
+        }
+
+        let checks = conditions
+            .into_iter()
+            .map(|condition| {
+                (
+                    StoreKey::to_key(zone_name, &condition.name, &condition.kind),
+                    condition.expect,
+                )
+            })
+            .collect::<Vec<(String, StoreConditionExpect)>>();
+
+        // Notice: conditions are checked in the same transaction as writes are applied, so \
+        //   that a concurrent write cannot slip in between.
+        let changes = self
+            .db
+            .transaction(|transaction| {
+                for (store_key, expect) in checks.iter() {
+                    let record = match transaction.get(store_key.as_bytes())? {
+                        Some(record_raw) => Some(
+                            serde_json::from_slice::<StoreRecord>(&record_raw)
+                                .or(abort(StoreError::Corrupted))?,
+                        ),
+                        None => None,
+                    };
+
+                    if expect.holds(record.as_ref().map(|record| record.to_version())) == false {
+                        return abort(StoreError::Conflict);
+                    }
+                }
+
+                let mut changes = Vec::new();
+
+                for write in writes.iter() {
+                    let record_previous_raw = match write.record {
+                        Some((_, ref record_raw)) => {
+                            transaction.insert(write.store_key.as_bytes(), record_raw.to_owned())?
+                        }
+                        None => transaction.remove(write.store_key.as_bytes())?,
+                    };
+
+                    let record_previous = match record_previous_raw {
+                        Some(record_previous_raw) => Some(
+                            serde_json::from_slice::<StoreRecord>(&record_previous_raw)
+                                .or(abort(StoreError::Corrupted))?,
+                        ),
+                        None => None,
+                    };
+
+                    changes.push(StoreChange {
+                        name: write.name.to_owned(),
+                        kind: write.kind.to_owned(),
+                        before: record_previous.to_owned(),
+                        after: write.record.as_ref().map(|(record, _)| record.to_owned()),
+                    });
+
+                    // Removing a non-existing record does not change anything
+                    if write.record.is_none() == true && record_previous.is_none() == true {
+                        continue;
+                    }
+
+                    if APP_CONF.store.history_limit > 0 {
+                        let mut history = match transaction.get(write.history_key.as_bytes())? {
+                            Some(history_raw) => {
+                                serde_json::from_slice::<Vec<StoreRevision>>(&history_raw)
+                                    .or(abort(StoreError::Corrupted))?
+                            }
+                            None => Vec::new(),
+                        };
+
+                        let revision = history.first().map(|last| last.revision + 1).unwrap_or(1);
+
+                        history.insert(0, actor.to_revision(revision, record_previous));
+                        history.truncate(APP_CONF.store.history_limit);
+
+                        transaction.insert(
+                            write.history_key.as_bytes(),
+                            serde_json::to_vec(&history)
+                                .or_else(|err| abort(StoreError::Encoding(err)))?,
+                        )?;
+                    }
+                }
+
+                Ok(changes)
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => StoreError::Embedded(err),
+            })?;
+
+        self.flush().map(|_| changes)
+    }
+
+    fn history(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<Vec<StoreRevision>, StoreError> {
+        let history_key = StoreKey::to_history_key(zone_name, record_name, record_type);
+
+        match self.db.get(&history_key) {
+            Ok(Some(history_raw)) => {
+                serde_json::from_slice(&history_raw).or(Err(StoreError::Corrupted))
+            }
+            Ok(None) => Ok(Vec::new()),
+            Err(err) => Err(StoreError::Embedded(err)),
+        }
+    }
+
+    fn list(&self, zone_name: &ZoneName) -> Result<Vec<StoreRecord>, StoreError> {
+        let mut records = Vec::new();
+
+        for entry in self.db.scan_prefix(StoreKey::to_zone_prefix(zone_name)) {
+            let (_, record_raw) = entry.map_err(|err| StoreError::Embedded(err))?;
+
+            records.push(serde_json::from_slice(&record_raw).or(Err(StoreError::Corrupted))?);
+        }
+
+        Ok(records)
+    }
+
+    fn index(
+        &self,
+        zone_name: &ZoneName,
+        name_prefix: Option<&str>,
+    ) -> Result<Vec<StoreIndexEntry>, StoreError> {
+        // Notice: keys are hashed, thus all records of the zone need to be read back and \
+        //   filtered; the embedded store is not meant to hold large zones anyway.
+        let records = self.list(zone_name)?;
+
+        Ok(StoreIndexEntry::from_records(records.iter(), name_prefix))
+    }
+}
+
+impl StoreEmbedded {
+    fn flush(&self) -> Result<(), StoreError> {
+        // Notice: flush on each write, as record changes are rare, and must survive a crash \
+        //   right after the API acknowledged them.
+        self.db
+            .flush()
+            .map(|_| ())
+            .map_err(|err| StoreError::Embedded(err))
+    }
+}
@@ -0,0 +1,89 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use redis::{Client, RedisError};
+use std::thread;
+use std::time::Duration;
+
+use super::cache::STORE_CACHE;
+use crate::APP_CONF;
+use crate::APP_STORE;
+
+pub struct StoreInvalidateBuilder;
+pub struct StoreInvalidate;
+
+const INVALIDATE_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const INVALIDATE_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl StoreInvalidateBuilder {
+    pub fn new() -> StoreInvalidate {
+        StoreInvalidate {}
+    }
+}
+
+impl StoreInvalidate {
+    pub fn run(&self) {
+        info!("store invalidation subscriber is now active");
+
+        loop {
+            if let Err(err) = Self::subscribe() {
+                warn!(
+                    "store invalidation subscription dropped, reconnecting: {}",
+                    err
+                );
+            }
+
+            // Hold before reconnecting (prevents reconnect loop floods)
+            thread::sleep(INVALIDATE_RECONNECT_INTERVAL);
+        }
+    }
+
+    fn subscribe() -> Result<(), RedisError> {
+        let master_info = APP_STORE
+            .remote()
+            .and_then(|remote| remote.master_connection_info())
+            .ok_or_else(|| {
+                RedisError::from((redis::ErrorKind::IoError, "no store master available"))
+            })?;
+
+        debug!(
+            "subscribing to store invalidations at: {}",
+            master_info.addr
+        );
+
+        let mut connection = Client::open(master_info)?.get_connection()?;
+        let mut pubsub = connection.as_pubsub();
+
+        pubsub.set_read_timeout(Some(INVALIDATE_READ_TIMEOUT))?;
+        pubsub.subscribe(&APP_CONF.redis.invalidate_channel)?;
+
+        info!(
+            "subscribed to store invalidations on channel: {}",
+            APP_CONF.redis.invalidate_channel
+        );
+
+        // Invalidations may have been missed while the subscription was down, thus all cached \
+        //   records must be refreshed as soon as possible.
+        STORE_CACHE.mark_refresh_all();
+
+        loop {
+            match pubsub.get_message() {
+                Ok(message) => {
+                    let store_key: String = message.get_payload()?;
+
+                    debug!("got store invalidation for key: {}", store_key);
+
+                    STORE_CACHE.pop(&store_key);
+                }
+                Err(ref err) if err.is_timeout() == true => {
+                    // No invalidation received in a while; this is expected, keep listening
+                    debug!("no store invalidation received, still listening");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
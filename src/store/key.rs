@@ -8,10 +8,14 @@ use farmhash;
 
 use crate::dns::record::{RecordName, RecordType};
 use crate::dns::zone::ZoneName;
+use crate::APP_CONF;
 
 pub struct StoreKey;
 
 pub static KEY_PREFIX: &'static str = "cl";
+pub static KEY_PREFIX_HISTORY: &'static str = "clh";
+pub static KEY_INDEX: &'static str = "index";
+pub static KEY_INDEX_MARKER: &'static str = "indexed";
 
 impl StoreKey {
     pub fn to_key(
@@ -35,6 +39,50 @@ impl StoreKey {
         key
     }
 
+    pub fn to_history_key(
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> String {
+        // Notice: history keys use their own prefix, so that they do not match the zone pattern
+        format!(
+            "{}:{}",
+            KEY_PREFIX_HISTORY,
+            Self::hash(zone_name, record_name, record_type)
+        )
+    }
+
+    pub fn to_index_key(zone_name: &ZoneName) -> String {
+        format!("{}{}", Self::to_zone_prefix(zone_name), KEY_INDEX)
+    }
+
+    pub fn to_index_marker_key(zone_name: &ZoneName) -> String {
+        format!("{}{}", Self::to_zone_prefix(zone_name), KEY_INDEX_MARKER)
+    }
+
+    pub fn to_zone_pattern(zone_name: &ZoneName) -> String {
+        format!("{}*", Self::to_zone_prefix(zone_name))
+    }
+
+    pub fn to_zone_prefix(zone_name: &ZoneName) -> String {
+        format!("{}:{}:", KEY_PREFIX, Self::hash_zone(zone_name))
+    }
+
+    fn hash_zone(zone_name: &ZoneName) -> String {
+        let zone_hash = format!(
+            "{:x}",
+            farmhash::fingerprint32(zone_name.to_str().as_bytes())
+        );
+
+        // Notice: in cluster mode, the zone hash is wrapped in a hash tag, so that all keys for \
+        //   a given zone get stored in the same slot (this allows for multi-key operations).
+        if APP_CONF.redis.cluster.is_some() == true {
+            format!("{{{}}}", zone_hash)
+        } else {
+            zone_hash
+        }
+    }
+
     fn hash(zone_name: &ZoneName, record_name: &RecordName, record_type: &RecordType) -> String {
         debug!(
             "hashing record: {} on type: {} for zone: {}",
@@ -44,8 +92,8 @@ impl StoreKey {
         );
 
         format!(
-            "{:x}:{:x}:{}",
-            farmhash::fingerprint32(zone_name.to_str().as_bytes()),
+            "{}:{:x}:{}",
+            Self::hash_zone(zone_name),
             farmhash::fingerprint32(record_name.to_str().as_bytes()),
             record_type.to_str()
         )
@@ -16,7 +16,7 @@ macro_rules! get_cache_store_client {
             // Attempt to get the first healthy pool, in order
             match pool.try_get() {
                 Some(mut $client) => {
-                    debug!("acquired cache store client at: {}", target);
+                    debug!("acquired cache store client at: {}", target.addr);
 
                     // Healthy pool acquired, return immediately (break the acquire loop)
                     return $code;
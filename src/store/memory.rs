@@ -0,0 +1,201 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+use super::key::StoreKey;
+use super::store::{
+    RecordStore, StoreActor, StoreBatchOperation, StoreChange, StoreCondition, StoreError,
+    StoreIndexEntry, StoreRecord, StoreRevision,
+};
+use crate::dns::record::{RecordName, RecordType};
+use crate::dns::zone::ZoneName;
+
+use crate::APP_CONF;
+
+pub struct StoreMemoryBuilder;
+
+pub struct StoreMemory {
+    records: RwLock<HashMap<String, StoreRecord>>,
+    histories: RwLock<HashMap<String, VecDeque<StoreRevision>>>,
+}
+
+impl StoreMemoryBuilder {
+    pub fn new() -> StoreMemory {
+        info!("using in-memory store (records will not persist across restarts)");
+
+        StoreMemory {
+            records: RwLock::new(HashMap::new()),
+            histories: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl RecordStore for StoreMemory {
+    fn check(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<(), StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        if self.records.read().unwrap().contains_key(&store_key) == true {
+            Ok(())
+        } else {
+            Err(StoreError::NotFound)
+        }
+    }
+
+    fn get(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        self.records
+            .read()
+            .unwrap()
+            .get(&store_key)
+            .map(|record| record.to_owned())
+            .ok_or(StoreError::NotFound)
+    }
+
+    fn batch(
+        &self,
+        zone_name: &ZoneName,
+        operations: Vec<StoreBatchOperation>,
+        conditions: Vec<StoreCondition>,
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError> {
+        // Notice: the write lock is held for the whole batch, so that readers never see a \
+        //   partially-applied batch.
+        let mut records = self.records.write().unwrap();
+        let mut histories = self.histories.write().unwrap();
+
+        for condition in conditions {
+            let version = records
+                .get(&StoreKey::to_key(
+                    zone_name,
+                    &condition.name,
+                    &condition.kind,
+                ))
+                .map(|record| record.to_version());
+
+            if condition.expect.holds(version) == false {
+                return Err(StoreError::Conflict);
+            }
+        }
+
+        let mut changes = Vec::new();
+
+        for operation in operations {
+            let (history_key, record_previous) = match operation {
+                StoreBatchOperation::Set(mut record) => {
+                    record.renew_version();
+
+                    let record_previous = records.insert(
+                        StoreKey::to_key(zone_name, &record.name, &record.kind),
+                        record.to_owned(),
+                    );
+
+                    changes.push(StoreChange {
+                        name: record.name.to_owned(),
+                        kind: record.kind.to_owned(),
+                        before: record_previous.to_owned(),
+                        after: Some(record.to_owned()),
+                    });
+
+                    (
+                        StoreKey::to_history_key(zone_name, &record.name, &record.kind),
+                        record_previous,
+                    )
+                }
+                StoreBatchOperation::Remove(record_name, record_type) => {
+                    let record_previous =
+                        records.remove(&StoreKey::to_key(zone_name, &record_name, &record_type));
+
+                    changes.push(StoreChange {
+                        name: record_name.to_owned(),
+                        kind: record_type.to_owned(),
+                        before: record_previous.to_owned(),
+                        after: None,
+                    });
+
+                    // Removing a non-existing record does not change anything
+                    if record_previous.is_none() == true {
+                        continue;
+                    }
+
+                    (
+                        StoreKey::to_history_key(zone_name, &record_name, &record_type),
+                        record_previous,
+                    )
+                }
+            };
+
+            if APP_CONF.store.history_limit > 0 {
+                let history = histories.entry(history_key).or_insert_with(VecDeque::new);
+                let revision = history.front().map(|last| last.revision + 1).unwrap_or(1);
+
+                history.push_front(actor.to_revision(revision, record_previous));
+                history.truncate(APP_CONF.store.history_limit);
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn history(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<Vec<StoreRevision>, StoreError> {
+        let history_key = StoreKey::to_history_key(zone_name, record_name, record_type);
+
+        Ok(self
+            .histories
+            .read()
+            .unwrap()
+            .get(&history_key)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn list(&self, zone_name: &ZoneName) -> Result<Vec<StoreRecord>, StoreError> {
+        let zone_prefix = StoreKey::to_zone_prefix(zone_name);
+
+        Ok(self
+            .records
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(store_key, _)| store_key.starts_with(&zone_prefix))
+            .map(|(_, record)| record.to_owned())
+            .collect())
+    }
+
+    fn index(
+        &self,
+        zone_name: &ZoneName,
+        name_prefix: Option<&str>,
+    ) -> Result<Vec<StoreIndexEntry>, StoreError> {
+        let zone_prefix = StoreKey::to_zone_prefix(zone_name);
+        let records = self.records.read().unwrap();
+
+        Ok(StoreIndexEntry::from_records(
+            records
+                .iter()
+                .filter(|(store_key, _)| store_key.starts_with(&zone_prefix))
+                .map(|(_, record)| record),
+            name_prefix,
+        ))
+    }
+}
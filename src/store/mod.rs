@@ -7,8 +7,15 @@
 #[macro_use]
 mod macros;
 
-mod cache;
 mod key;
 
+pub mod cache;
+pub mod connector;
+pub mod embedded;
 pub mod flush;
+pub mod invalidate;
+pub mod memory;
+pub mod remote;
+pub mod sentinel;
+pub mod warmup;
 pub mod store;
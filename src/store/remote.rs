@@ -0,0 +1,1140 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use r2d2::Pool;
+use redis::cluster_routing::get_slot;
+use redis::{self, Commands, Connection, ConnectionInfo, ErrorKind, Pipeline, RedisError, Value};
+use serde_json;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+
+use super::cache::STORE_CACHE;
+use super::connector::{StoreConnectionManager, StoreConnectionTarget};
+use super::key::StoreKey;
+use super::sentinel::StoreSentinel;
+use super::store::{
+    RecordStore, StoreActor, StoreBatchOperation, StoreChange, StoreCondition,
+    StoreConditionExpect, StoreError, StoreIndexEntry, StoreRecord, StoreRevision, VERSION_DEFAULT,
+};
+use crate::dns::record::{RecordBlackhole, RecordName, RecordRegions, RecordType, RecordValues};
+use crate::dns::zone::ZoneName;
+
+use crate::APP_CONF;
+
+static KEY_ZONE: &'static str = "z";
+static KEY_TYPE: &'static str = "t";
+static KEY_NAME: &'static str = "n";
+static KEY_TTL: &'static str = "e";
+static KEY_BLACKHOLE: &'static str = "b";
+static KEY_REGION: &'static str = "r";
+static KEY_RESCUE: &'static str = "f"; // Alias for 'failover'
+static KEY_VALUE: &'static str = "v";
+static KEY_VERSION: &'static str = "w";
+static KEY_STREAM_PAYLOAD: &'static str = "p";
+
+type StoreGetType = (
+    String,
+    String,
+    u32,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+    String,
+    Option<String>,
+);
+
+type StorePoolType = (Pool<StoreConnectionManager>, ConnectionInfo);
+
+static BATCH_ATTEMPTS_MAXIMUM: u8 = 3;
+
+pub struct StoreRemoteBuilder;
+
+pub struct StoreRemote {
+    pools: RwLock<Vec<StorePoolType>>,
+}
+
+struct StoreRemoteChange {
+    name: RecordName,
+    kind: RecordType,
+    record: Option<StoreRecord>,
+    store_key: String,
+    history_key: String,
+    index_member: String,
+    fields: Option<Vec<(&'static str, String)>>,
+}
+
+impl StoreRemoteBuilder {
+    pub fn new() -> StoreRemote {
+        match Self::pools_bind() {
+            Ok(pools) => StoreRemote {
+                pools: RwLock::new(pools),
+            },
+            Err(err) => panic!(err),
+        }
+    }
+
+    fn pools_bind() -> Result<Vec<StorePoolType>, &'static str> {
+        let mut pools = Vec::new();
+
+        if APP_CONF.redis.sentinel.is_some() == true && APP_CONF.redis.cluster.is_some() == true {
+            return Err("redis sentinel and cluster modes cannot be used together");
+        }
+
+        let master_target = StoreConnectionTarget::from(&APP_CONF.redis.master);
+
+        if let Some(ref cluster) = APP_CONF.redis.cluster {
+            // Bind to cluster pool (commands get routed to slot owners)
+            pools.push(Self::pool_connect_cluster(&cluster.nodes, &master_target)?);
+        } else if let Some(ref sentinel) = APP_CONF.redis.sentinel {
+            // Bind to master and replica pools (as discovered from Sentinel)
+            let (master, replicas) = StoreSentinel::discover(sentinel)?;
+
+            pools.push(Self::pool_connect(
+                &master_target.with_address(&master.0, master.1),
+            )?);
+
+            for replica in replicas {
+                // Notice: a failing replica is not fatal, as replicas are only used as rescue
+                match Self::pool_connect(&master_target.with_address(&replica.0, replica.1)) {
+                    Ok(replica_pool) => pools.push(replica_pool),
+                    Err(err) => warn!(
+                        "could not bind to store replica at {}:{}: {}",
+                        replica.0, replica.1, err
+                    ),
+                }
+            }
+        } else {
+            // Bind to master pool
+            pools.push(Self::pool_connect(&master_target)?);
+        }
+
+        // Bind to rescue pools (if any)
+        if let Some(ref rescue_items) = APP_CONF.redis.rescue {
+            for rescue in rescue_items {
+                pools.push(Self::pool_connect(&StoreConnectionTarget::from(rescue))?);
+            }
+        }
+
+        Ok(pools)
+    }
+
+    fn pool_connect(target: &StoreConnectionTarget) -> Result<StorePoolType, &'static str> {
+        let client = target
+            .client()
+            .or(Err("could not create redis connection manager"))?;
+        let connection_info = client.get_connection_info().to_owned();
+
+        info!("binding to store backend at {}", connection_info.addr);
+
+        Self::pool_build(StoreConnectionManager::Node(client), connection_info)
+    }
+
+    fn pool_connect_cluster(
+        nodes: &[String],
+        target: &StoreConnectionTarget,
+    ) -> Result<StorePoolType, &'static str> {
+        info!("binding to store cluster at {}", nodes.join(", "));
+
+        if APP_CONF.redis.database != 0 {
+            warn!("redis cluster only supports database 0, ignoring configured database");
+        }
+
+        // Notice: the first node is used as the pool target, which is also used to subscribe \
+        //   to Pub/Sub channels (as published messages get broadcast to all cluster nodes).
+        let first_node = nodes.first().ok_or("no redis cluster node configured")?;
+
+        let mut first_node_parts = first_node.rsplitn(2, ":");
+
+        let first_node_port = first_node_parts
+            .next()
+            .and_then(|port| port.parse::<u16>().ok())
+            .ok_or("invalid redis cluster node address")?;
+        let first_node_host = first_node_parts
+            .next()
+            .ok_or("invalid redis cluster node address")?;
+
+        let connection_info = target
+            .with_address(first_node_host, first_node_port)
+            .client()
+            .or(Err("could not create redis cluster connection manager"))?
+            .get_connection_info()
+            .to_owned();
+
+        match StoreConnectionManager::cluster(nodes, target) {
+            Ok(manager) => Self::pool_build(manager, connection_info),
+            Err(_) => Err("could not create redis cluster connection manager"),
+        }
+    }
+
+    fn pool_build(
+        manager: StoreConnectionManager,
+        connection_info: ConnectionInfo,
+    ) -> Result<StorePoolType, &'static str> {
+        let builder = Pool::builder()
+            .test_on_check_out(true)
+            .max_size(APP_CONF.redis.pool_size)
+            .max_lifetime(Some(Duration::from_secs(
+                APP_CONF.redis.max_lifetime_seconds,
+            )))
+            .idle_timeout(Some(Duration::from_secs(
+                APP_CONF.redis.idle_timeout_seconds,
+            )))
+            .connection_timeout(Duration::from_secs(
+                APP_CONF.redis.connection_timeout_seconds,
+            ));
+
+        match builder.build(manager) {
+            Ok(pool) => {
+                info!("connected to redis at: {}", connection_info.addr);
+
+                Ok((pool, connection_info))
+            }
+            Err(_) => Err("could not spawn redis pool"),
+        }
+    }
+}
+
+impl RecordStore for StoreRemote {
+    fn check(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<(), StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        // Check from local cache?
+        if STORE_CACHE.has(&store_key) == true {
+            return Ok(());
+        }
+
+        // Check from store
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.exists::<&str, bool>(&store_key)
+            .map_err(|err| {
+                StoreError::Connector(err)
+            })
+            .and_then(|exists| {
+                if exists == true {
+                    Ok(())
+                } else {
+                    // Store in local cache (no value)
+                    STORE_CACHE.push(&store_key, None, None);
+
+                    // Consider as not found
+                    Err(StoreError::NotFound)
+                }
+            })
+        })
+    }
+
+    fn get(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        // Get from local cache?
+        if let Ok(cached_records) = STORE_CACHE.get(&store_key) {
+            return match cached_records {
+                Some(cached_records) => Ok(cached_records),
+                None => Err(StoreError::NotFound),
+            };
+        }
+
+        // Get from store
+        self.raw_get_remote(&store_key, None)
+    }
+
+    fn get_fresh(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        // Get from store only (the local cache gets refreshed along the way)
+        self.raw_get_remote(&store_key, None)
+    }
+
+    fn get_stale(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError> {
+        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
+
+        // Get from local cache only (including stale records)
+        // Notice: if the record is unknown to the local cache, consider the store as \
+        //   disconnected, as it cannot be told whether the record exists or not.
+        match STORE_CACHE.get_stale(&store_key) {
+            Ok(Some(cached_records)) => Ok(cached_records),
+            Ok(None) => Err(StoreError::NotFound),
+            Err(_) => Err(StoreError::Disconnected),
+        }
+    }
+
+    fn batch(
+        &self,
+        zone_name: &ZoneName,
+        operations: Vec<StoreBatchOperation>,
+        conditions: Vec<StoreCondition>,
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError> {
+        let mut changes = Vec::new();
+
+        // Notice: records are all encoded first, so that nothing gets written if any of them \
+        //   fails to encode.
+        for operation in operations {
+            match operation {
+                StoreBatchOperation::Set(mut record) => {
+                    record.renew_version();
+
+                    changes.push(StoreRemoteChange {
+                        store_key: StoreKey::to_key(zone_name, &record.name, &record.kind),
+                        history_key: StoreKey::to_history_key(
+                            zone_name,
+                            &record.name,
+                            &record.kind,
+                        ),
+                        index_member: StoreIndexEntry {
+                            name: record.name.to_owned(),
+                            kind: record.kind.to_owned(),
+                        }
+                        .to_member(),
+                        fields: Some(Self::encode_record(zone_name, &record)?),
+                        name: record.name.to_owned(),
+                        kind: record.kind.to_owned(),
+                        record: Some(record),
+                    });
+                }
+                StoreBatchOperation::Remove(record_name, record_type) => {
+                    changes.push(StoreRemoteChange {
+                        store_key: StoreKey::to_key(zone_name, &record_name, &record_type),
+                        history_key: StoreKey::to_history_key(
+                            zone_name,
+                            &record_name,
+                            &record_type,
+                        ),
+                        index_member: StoreIndexEntry {
+                            name: record_name.to_owned(),
+                            kind: record_type.to_owned(),
+                        }
+                        .to_member(),
+                        fields: None,
+                        name: record_name,
+                        kind: record_type,
+                        record: None,
+                    });
+                }
+            }
+        }
+
+        if changes.is_empty() == true {
+            return Ok(Vec::new());
+        }
+
+        let checks = conditions
+            .into_iter()
+            .map(|condition| {
+                (
+                    StoreKey::to_key(zone_name, &condition.name, &condition.kind),
+                    condition.expect,
+                )
+            })
+            .collect::<Vec<(String, StoreConditionExpect)>>();
+
+        self.batch_apply(&StoreKey::to_index_key(zone_name), &changes, &checks, actor)
+    }
+
+    fn history(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<Vec<StoreRevision>, StoreError> {
+        let history_key = StoreKey::to_history_key(zone_name, record_name, record_type);
+
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.lrange::<_, Vec<String>>(&history_key, 0, -1)
+                .map_err(|err| {
+                    StoreError::Connector(err)
+                })
+                .and_then(|revisions_raw| {
+                    revisions_raw
+                        .iter()
+                        .map(|revision_raw| {
+                            serde_json::from_str(revision_raw).or(Err(StoreError::Corrupted))
+                        })
+                        .collect()
+                })
+        })
+    }
+
+    fn list(&self, zone_name: &ZoneName) -> Result<Vec<StoreRecord>, StoreError> {
+        let mut records = Vec::new();
+
+        for entry in self.index(zone_name, None)? {
+            let store_key = StoreKey::to_key(zone_name, &entry.name, &entry.kind);
+
+            match self.raw_get_remote(&store_key, None) {
+                Ok(record) => records.push(record),
+                Err(StoreError::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn index(
+        &self,
+        zone_name: &ZoneName,
+        name_prefix: Option<&str>,
+    ) -> Result<Vec<StoreIndexEntry>, StoreError> {
+        let index_key = StoreKey::to_index_key(zone_name);
+
+        // Records written before zone indexes were introduced are not indexed yet; rebuild the \
+        //   index from existing keys then.
+        self.ensure_index(zone_name, &index_key)?;
+
+        // Notice: all members have the same score, thus they are lexicographically ordered, \
+        //   which allows name prefixes to be matched as a range (0xFF never appears in UTF-8).
+        let (range_min, range_max) = match name_prefix {
+            Some(name_prefix) => {
+                let range_min = format!("[{}", name_prefix).into_bytes();
+                let mut range_max = range_min.clone();
+
+                range_max.push(0xFF);
+
+                (range_min, range_max)
+            }
+            None => (b"-".to_vec(), b"+".to_vec()),
+        };
+
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.zrangebylex::<_, _, _, Vec<String>>(&index_key, range_min, range_max)
+                .map(|members| {
+                    members
+                        .iter()
+                        .filter_map(|member| StoreIndexEntry::from_member(member))
+                        .collect()
+                })
+                .map_err(|err| {
+                    StoreError::Connector(err)
+                })
+        })
+    }
+
+    fn remote(&self) -> Option<&StoreRemote> {
+        Some(self)
+    }
+}
+
+impl StoreRemote {
+    pub fn warmup(&self, zone_name: &ZoneName, deadline: Instant) -> Result<usize, StoreError> {
+        let mut count = 0;
+
+        // Notice: index entries are listed first, then loaded one by one, as loading a key \
+        //   requires its own store client.
+        for entry in self.index(zone_name, None)? {
+            let store_key = StoreKey::to_key(zone_name, &entry.name, &entry.kind);
+
+            // Deadline reached? Abort there (keep already-loaded records)
+            if Instant::now() >= deadline {
+                break;
+            }
+
+            // Load record in local cache (from remote)
+            if self.raw_get_remote(&store_key, None).is_ok() {
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn ensure_index(&self, zone_name: &ZoneName, index_key: &str) -> Result<(), StoreError> {
+        let marker_key = StoreKey::to_index_marker_key(zone_name);
+
+        // Notice: Redis deletes sorted sets once they get empty, thus a missing index does not \
+        //   tell whether the zone is empty or whether its index was never built. A marker key \
+        //   is written once the index got rebuilt, so that empty zones are not scanned again.
+        if self.index_exists(index_key, &marker_key)? == true {
+            return Ok(());
+        }
+
+        // Notice: zone keys cannot be scanned across a cluster, as 'SCAN' only iterates over the \
+        //   keys of a single node. Indexes are maintained upon every write in cluster mode, thus \
+        //   an index can only be missing if the zone is empty, or if records were written before \
+        //   cluster mode was enabled (key names change in cluster mode, so those records are not \
+        //   reachable anyway). The index is marked, so that this is only reported once.
+        if APP_CONF.redis.cluster.is_some() == true {
+            warn!(
+                "cannot rebuild store index in redis cluster mode for zone: {} (records written \
+                    before cluster mode was enabled need to be imported again)",
+                zone_name.to_str()
+            );
+
+            return self.index_mark(&marker_key);
+        }
+
+        info!("rebuilding store index for zone: {}", zone_name.to_str());
+
+        let mut members = Vec::new();
+
+        for store_key in self.scan_zone_keys(zone_name)? {
+            // Skip the index and marker keys themselves (they match the zone pattern)
+            if store_key == index_key || store_key == marker_key {
+                continue;
+            }
+
+            match self.raw_get_remote(&store_key, None) {
+                Ok(record) => {
+                    members.push((
+                        0,
+                        StoreIndexEntry {
+                            name: record.name,
+                            kind: record.kind,
+                        }
+                        .to_member(),
+                    ));
+                }
+                Err(StoreError::NotFound) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if members.is_empty() == false {
+            self.index_insert(index_key, &members)?;
+        }
+
+        self.index_mark(&marker_key)?;
+
+        info!(
+            "rebuilt store index for zone: {} (count: {})",
+            zone_name.to_str(),
+            members.len()
+        );
+
+        Ok(())
+    }
+
+    fn index_exists(&self, index_key: &str, marker_key: &str) -> Result<bool, StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client
+                .exists::<_, usize>(&[index_key, marker_key][..])
+                .map(|count| count > 0)
+                .map_err(|err| StoreError::Connector(err))
+        })
+    }
+
+    fn index_mark(&self, marker_key: &str) -> Result<(), StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.set::<_, _, ()>(marker_key, 1).map_err(|err| {
+                StoreError::Connector(err)
+            })
+        })
+    }
+
+    fn index_insert(&self, index_key: &str, members: &[(i64, String)]) -> Result<(), StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.zadd_multiple::<_, _, _, ()>(index_key, members).map_err(|err| {
+                StoreError::Connector(err)
+            })
+        })
+    }
+
+    fn scan_zone_keys(&self, zone_name: &ZoneName) -> Result<Vec<String>, StoreError> {
+        let zone_pattern = StoreKey::to_zone_pattern(zone_name);
+
+        debug!("scanning store keys matching: {}", zone_pattern);
+
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            client.scan_match::<_, String>(&zone_pattern)
+                .map(|store_keys| store_keys.collect())
+                .map_err(|err| {
+                    StoreError::Connector(err)
+                })
+        })
+    }
+
+    pub fn stream_append(
+        &self,
+        stream_key: &str,
+        max_length: usize,
+        payload: &str,
+    ) -> Result<(), StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            // Notice: the stream is trimmed approximately, which is much cheaper than exact \
+            //   trimming (it may hold slightly more entries than its maximum length).
+            redis::cmd("XADD")
+                .arg(stream_key)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(max_length)
+                .arg("*")
+                .arg(KEY_STREAM_PAYLOAD)
+                .arg(payload)
+                .query::<String>(&mut *client)
+                .map(|_| ())
+                .map_err(|err| StoreError::Connector(err))
+        })
+    }
+
+    pub fn stream_range_reverse(
+        &self,
+        stream_key: &str,
+        start_id: &str,
+        end_id: &str,
+        count: usize,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        // Notice: entries are returned from the most recent to the oldest
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            redis::cmd("XREVRANGE")
+                .arg(stream_key)
+                .arg(end_id)
+                .arg(start_id)
+                .arg("COUNT")
+                .arg(count)
+                .query::<Vec<(String, HashMap<String, String>)>>(&mut *client)
+                .map(|entries| {
+                    entries
+                        .into_iter()
+                        .filter_map(|(entry_id, mut fields)| {
+                            fields
+                                .remove(KEY_STREAM_PAYLOAD)
+                                .map(|payload| (entry_id, payload))
+                        })
+                        .collect()
+                })
+                .map_err(|err| StoreError::Connector(err))
+        })
+    }
+
+    pub fn master_connection_info(&self) -> Option<ConnectionInfo> {
+        // Notice: the first pool is always the master pool
+        self.pools
+            .read()
+            .unwrap()
+            .first()
+            .map(|(_, target)| target.to_owned())
+    }
+
+    pub fn rebind(&self) -> Result<(), &'static str> {
+        info!("rebinding to store backend");
+
+        // Notice: new pools are built before acquiring the write lock, so that ongoing store \
+        //   operations are not blocked while connecting to the new targets.
+        let pools = StoreRemoteBuilder::pools_bind()?;
+
+        *self.pools.write().unwrap() = pools;
+
+        info!("rebound to store backend");
+
+        Ok(())
+    }
+
+    fn encode_record(
+        zone_name: &ZoneName,
+        record: &StoreRecord,
+    ) -> Result<Vec<(&'static str, String)>, StoreError> {
+        let blackhole_encoder = match record.blackhole {
+            Some(ref blackhole) => {
+                if blackhole.has_items() == true {
+                    serde_json::to_string(blackhole)
+                } else {
+                    Ok("".to_owned())
+                }
+            }
+            None => Ok("".to_owned()),
+        };
+        let region_encoder = match record.regions {
+            Some(ref regions) => serde_json::to_string(regions),
+            None => Ok("".to_owned()),
+        };
+        let rescue_encoder = match record.rescue {
+            Some(ref rescue) => {
+                if rescue.is_empty() == false {
+                    serde_json::to_string(rescue)
+                } else {
+                    Ok("".to_owned())
+                }
+            }
+            None => Ok("".to_owned()),
+        };
+
+        match (
+            serde_json::to_string(&record.values),
+            blackhole_encoder,
+            region_encoder,
+            rescue_encoder,
+        ) {
+            (Ok(values), Ok(blackhole), Ok(regions), Ok(rescue)) => Ok(vec![
+                (KEY_ZONE, zone_name.to_str().to_owned()),
+                (KEY_TYPE, record.kind.to_str().to_owned()),
+                (KEY_NAME, record.name.to_str().to_owned()),
+                (KEY_TTL, record.ttl.unwrap_or(0).to_string()),
+                (KEY_BLACKHOLE, blackhole),
+                (KEY_REGION, regions),
+                (KEY_RESCUE, rescue),
+                (KEY_VALUE, values),
+                (KEY_VERSION, record.to_version().to_owned()),
+            ]),
+            (Err(err), _, _, _)
+            | (_, Err(err), _, _)
+            | (_, _, Err(err), _)
+            | (_, _, _, Err(err)) => Err(StoreError::Encoding(err)),
+        }
+    }
+
+    fn batch_apply(
+        &self,
+        index_key: &str,
+        changes: &[StoreRemoteChange],
+        checks: &[(String, StoreConditionExpect)],
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            if APP_CONF.redis.cluster.is_some() == true {
+                // Notice: commands sent over a cluster connection may be served by different \
+                //   nodes (eg. upon a redirection or a reconnection), which would silently drop \
+                //   the watch on keys. All keys of a zone share the same hash tag, thus the \
+                //   transaction is run over a connection to the node owning their slot.
+                let mut node_client = Self::cluster_slot_connection(&mut *client, index_key)?;
+
+                Self::batch_transact(&mut node_client, index_key, changes, checks, actor)
+            } else {
+                Self::batch_transact(&mut *client, index_key, changes, checks, actor)
+            }
+        })
+    }
+
+    fn batch_transact<C: Commands>(
+        client: &mut C,
+        index_key: &str,
+        changes: &[StoreRemoteChange],
+        checks: &[(String, StoreConditionExpect)],
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError> {
+        // Notice: all operations get queued in a single 'MULTI' / 'EXEC' transaction, along \
+        //   with their zone index and history entries. Keys are watched before being read, so \
+        //   that the transaction gets aborted if any of them is changed until it gets executed; \
+        //   the batch is then attempted again.
+        for _ in 0..BATCH_ATTEMPTS_MAXIMUM {
+            let watch_keys = changes
+                .iter()
+                .map(|change| change.store_key.as_str())
+                .chain(checks.iter().map(|(store_key, _)| store_key.as_str()))
+                .collect::<Vec<&str>>();
+
+            redis::cmd("WATCH")
+                .arg(watch_keys)
+                .query::<()>(client)
+                .map_err(|err| StoreError::Connector(err))?;
+
+            // Check conditions, then read previous records for history
+            let prepared = Self::batch_check(client, checks)
+                .and_then(|_| Self::batch_prepare(client, index_key, changes, actor));
+
+            let (pipeline, applied_changes) = match prepared {
+                Ok(prepared) => prepared,
+                Err(err) => {
+                    // Release watched keys, as the transaction will not be executed
+                    let _ = redis::cmd("UNWATCH").query::<()>(client);
+
+                    return Err(err);
+                }
+            };
+
+            // Apply in remote
+            // Notice: an aborted transaction (ie. a watched key changed) returns nothing.
+            let applied = pipeline
+                .query::<Option<()>>(client)
+                .map_err(|err| StoreError::Connector(err))?;
+
+            if applied.is_some() == true {
+                // Clean from local cache, and invalidate from other replicas local cache
+                // Notice: this is done once the transaction got executed, otherwise a \
+                //   concurrent read could cache the previous record again in-between.
+                for change in changes {
+                    STORE_CACHE.pop(&change.store_key);
+
+                    Self::publish_invalidate(client, &change.store_key);
+                }
+
+                return Ok(applied_changes);
+            }
+
+            debug!("store batch transaction was aborted, attempting again");
+        }
+
+        Err(StoreError::Conflict)
+    }
+
+    fn cluster_slot_connection<C: Commands>(
+        client: &mut C,
+        store_key: &str,
+    ) -> Result<Connection, StoreError> {
+        let slot = get_slot(store_key.as_bytes());
+
+        let slots = redis::cmd("CLUSTER")
+            .arg("SLOTS")
+            .query::<Value>(client)
+            .map_err(|err| StoreError::Connector(err))?;
+
+        let (host, port) = Self::cluster_slot_owner(&slots, slot).ok_or_else(|| {
+            error!("could not find store cluster node owning slot: {}", slot);
+
+            StoreError::Disconnected
+        })?;
+
+        debug!(
+            "running store transaction on cluster node at {}:{} for slot: {}",
+            host, port, slot
+        );
+
+        StoreConnectionTarget::from(&APP_CONF.redis.master)
+            .with_address(&host, port)
+            .client()
+            .and_then(|node_client| node_client.get_connection())
+            .map_err(|err| StoreError::Connector(err))
+    }
+
+    fn cluster_slot_owner(slots: &Value, slot: u16) -> Option<(String, u16)> {
+        // Notice: each slot range is formatted as '[start, end, [host, port, ...], ...]', where \
+        //   the first node is the master owning the range, and next nodes are its replicas.
+        if let Value::Bulk(ranges) = slots {
+            for range in ranges {
+                if let Value::Bulk(range) = range {
+                    if let (
+                        Some(Value::Int(start)),
+                        Some(Value::Int(end)),
+                        Some(Value::Bulk(master)),
+                    ) = (range.get(0), range.get(1), range.get(2))
+                    {
+                        if (slot as i64) < *start || (slot as i64) > *end {
+                            continue;
+                        }
+
+                        return match (master.get(0), master.get(1)) {
+                            (Some(Value::Data(host)), Some(Value::Int(port)))
+                                if host.is_empty() == false =>
+                            {
+                                Some((String::from_utf8_lossy(host).into_owned(), *port as u16))
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    fn batch_check<C: Commands>(
+        client: &mut C,
+        checks: &[(String, StoreConditionExpect)],
+    ) -> Result<(), StoreError> {
+        for (store_key, expect) in checks {
+            let (exists, version) = redis::pipe()
+                .exists(store_key)
+                .hget(store_key, KEY_VERSION)
+                .query::<(bool, Option<String>)>(client)
+                .map_err(|err| StoreError::Connector(err))?;
+
+            let version = if exists == true {
+                Some(version.unwrap_or(VERSION_DEFAULT.to_owned()))
+            } else {
+                None
+            };
+
+            if expect.holds(version.as_ref().map(|version| version.as_str())) == false {
+                return Err(StoreError::Conflict);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn batch_prepare<C: Commands>(
+        client: &mut C,
+        index_key: &str,
+        changes: &[StoreRemoteChange],
+        actor: &StoreActor,
+    ) -> Result<(Pipeline, Vec<StoreChange>), StoreError> {
+        let mut pipeline = redis::pipe();
+        let mut applied_changes = Vec::new();
+
+        pipeline.atomic();
+
+        for change in changes {
+            match change.fields {
+                Some(ref fields) => {
+                    pipeline
+                        .hset_multiple(&change.store_key, fields)
+                        .ignore()
+                        .zadd(index_key, &change.index_member, 0)
+                        .ignore();
+                }
+                None => {
+                    pipeline
+                        .del(&change.store_key)
+                        .ignore()
+                        .zrem(index_key, &change.index_member)
+                        .ignore();
+                }
+            }
+
+            let record_previous = Self::read_record(client, &change.store_key)?;
+
+            applied_changes.push(StoreChange {
+                name: change.name.to_owned(),
+                kind: change.kind.to_owned(),
+                before: record_previous.to_owned(),
+                after: change.record.to_owned(),
+            });
+
+            // Removing a non-existing record does not change anything
+            if change.fields.is_none() == true && record_previous.is_none() == true {
+                continue;
+            }
+
+            if APP_CONF.store.history_limit > 0 {
+                let revision = match client
+                    .lindex::<_, Option<String>>(&change.history_key, 0)
+                    .map_err(|err| StoreError::Connector(err))?
+                {
+                    Some(last_raw) => {
+                        serde_json::from_str::<StoreRevision>(&last_raw)
+                            .or(Err(StoreError::Corrupted))?
+                            .revision
+                            + 1
+                    }
+                    None => 1,
+                };
+
+                let revision_raw =
+                    serde_json::to_string(&actor.to_revision(revision, record_previous))
+                        .map_err(|err| StoreError::Encoding(err))?;
+
+                pipeline
+                    .lpush(&change.history_key, revision_raw)
+                    .ignore()
+                    .ltrim(
+                        &change.history_key,
+                        0,
+                        APP_CONF.store.history_limit as isize - 1,
+                    )
+                    .ignore();
+            }
+        }
+
+        Ok((pipeline, applied_changes))
+    }
+
+    fn read_record<C: Commands>(
+        client: &mut C,
+        store_key: &str,
+    ) -> Result<Option<StoreRecord>, StoreError> {
+        let (exists, values) = redis::pipe()
+            .exists(store_key)
+            .hget(
+                store_key,
+                (
+                    KEY_TYPE,
+                    KEY_NAME,
+                    KEY_TTL,
+                    KEY_BLACKHOLE,
+                    KEY_REGION,
+                    KEY_RESCUE,
+                    KEY_VALUE,
+                    KEY_VERSION,
+                ),
+            )
+            .query::<(bool, Value)>(client)
+            .map_err(|err| StoreError::Connector(err))?;
+
+        if exists == true {
+            redis::from_redis_value::<StoreGetType>(&values)
+                .or(Err(StoreError::Corrupted))
+                .and_then(Self::decode_record)
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn publish_invalidate<C: Commands>(client: &mut C, store_key: &str) {
+        if APP_CONF.redis.invalidate_enable == true {
+            // Notice: a failed publish is not considered as a failed write, as replicas will \
+            //   eventually refresh their local cache anyway.
+            if let Err(err) =
+                client.publish::<_, _, ()>(&APP_CONF.redis.invalidate_channel, store_key)
+            {
+                warn!(
+                    "could not publish store invalidation for key: {}: {}",
+                    store_key, err
+                );
+            }
+        }
+    }
+
+    pub fn raw_get_remote(
+        &self,
+        store_key: &str,
+        cache_accessed_at: Option<SystemTime>,
+    ) -> Result<StoreRecord, StoreError> {
+        get_cache_store_client!(self.pools.read().unwrap().iter(), StoreError::Disconnected, client {
+            match client.hget::<_, _, StoreGetType>(
+                store_key,
+                (
+                    KEY_TYPE, KEY_NAME, KEY_TTL, KEY_BLACKHOLE, KEY_REGION, KEY_RESCUE, KEY_VALUE,
+                    KEY_VERSION,
+                ),
+            ) {
+                Ok(values) => {
+                    Self::decode_record(values).map(|record| {
+                        // Store in local cache
+                        STORE_CACHE.push(store_key, Some(record.clone()), cache_accessed_at);
+
+                        record
+                    })
+                },
+                Err(err) => {
+                    // Store in local cache? (no value)
+                    // Notice: do not store an empty cache if error is not a type error (meaning: \
+                    //   no such value exist; this avoids storing a blank cache entry for I/O \
+                    //   and network timeout errors, which would corrupt the cache)
+                    if err.kind() == ErrorKind::TypeError {
+                        STORE_CACHE.push(store_key, None, cache_accessed_at);
+                    }
+
+                    // Consider as not found
+                    Err(StoreError::NotFound)
+                },
+            }
+        })
+    }
+
+    fn decode_record(values: StoreGetType) -> Result<StoreRecord, StoreError> {
+        if let (Some(kind_value), Some(name_value), Ok(value_value)) = (
+            RecordType::from_str(&values.0),
+            RecordName::from_str(&values.1),
+            serde_json::from_str(&values.6),
+        ) {
+            let ttl = if values.2 > 0 { Some(values.2) } else { None };
+
+            let blackhole = values.3.and_then(|blackhole_raw| {
+                serde_json::from_str::<RecordBlackhole>(&blackhole_raw).ok()
+            });
+            let regions = values
+                .4
+                .and_then(|region_raw| serde_json::from_str::<RecordRegions>(&region_raw).ok());
+            let rescue = values
+                .5
+                .and_then(|rescue_raw| serde_json::from_str::<RecordValues>(&rescue_raw).ok());
+
+            debug!(
+                "read store record with kind: {:?}, name: {:?} and values: {:?}",
+                kind_value, name_value, value_value
+            );
+
+            if blackhole.is_some() == true {
+                debug!(
+                    "store record with kind: {:?}, name: {:?} has blackhole: {:?}",
+                    kind_value, name_value, blackhole
+                );
+            }
+            if regions.is_some() == true {
+                debug!(
+                    "store record with kind: {:?}, name: {:?} has regions: {:?}",
+                    kind_value, name_value, regions
+                );
+            }
+            if rescue.is_some() == true {
+                debug!(
+                    "store record with kind: {:?}, name: {:?} has rescue: {:?}",
+                    kind_value, name_value, rescue
+                );
+            }
+
+            Ok(StoreRecord {
+                kind: kind_value,
+                name: name_value,
+                ttl: ttl,
+                blackhole: blackhole,
+                regions: regions,
+                rescue: rescue,
+                values: value_value,
+                version: values.7,
+            })
+        } else {
+            Err(StoreError::Corrupted)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_node(host: &str, port: i64) -> Value {
+        Value::Bulk(vec![
+            Value::Data(host.as_bytes().to_vec()),
+            Value::Int(port),
+            Value::Data(b"node-id".to_vec()),
+        ])
+    }
+
+    #[test]
+    fn it_finds_cluster_slot_owner() {
+        let slots = Value::Bulk(vec![
+            Value::Bulk(vec![
+                Value::Int(0),
+                Value::Int(8191),
+                make_node("10.0.0.1", 7000),
+                make_node("10.0.0.3", 7002),
+            ]),
+            Value::Bulk(vec![
+                Value::Int(8192),
+                Value::Int(16383),
+                make_node("10.0.0.2", 7001),
+            ]),
+        ]);
+
+        assert_eq!(
+            StoreRemote::cluster_slot_owner(&slots, 0),
+            Some(("10.0.0.1".to_owned(), 7000))
+        );
+        assert_eq!(
+            StoreRemote::cluster_slot_owner(&slots, 8192),
+            Some(("10.0.0.2".to_owned(), 7001))
+        );
+        assert_eq!(
+            StoreRemote::cluster_slot_owner(&slots, 16383),
+            Some(("10.0.0.2".to_owned(), 7001))
+        );
+    }
+
+    #[test]
+    fn it_finds_no_cluster_slot_owner_on_uncovered_slots() {
+        let slots = Value::Bulk(vec![Value::Bulk(vec![
+            Value::Int(0),
+            Value::Int(100),
+            make_node("10.0.0.1", 7000),
+        ])]);
+
+        assert_eq!(StoreRemote::cluster_slot_owner(&slots, 101), None);
+        assert_eq!(StoreRemote::cluster_slot_owner(&Value::Nil, 0), None);
+    }
+}
@@ -0,0 +1,194 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use redis::{self, Client, Connection, RedisError};
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::config::ConfigRedisSentinel;
+use crate::APP_CONF;
+use crate::APP_STORE;
+
+pub struct StoreSentinelBuilder;
+pub struct StoreSentinel;
+
+type StoreSentinelTarget = (String, u16);
+
+const SENTINEL_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const SENTINEL_READ_TIMEOUT: Duration = Duration::from_secs(30);
+const SENTINEL_CHANNEL_SWITCH_MASTER: &'static str = "+switch-master";
+
+impl StoreSentinelBuilder {
+    pub fn new() -> StoreSentinel {
+        StoreSentinel {}
+    }
+}
+
+impl StoreSentinel {
+    pub fn run(&self) {
+        info!("store sentinel watcher is now active");
+
+        if let Some(ref sentinel) = APP_CONF.redis.sentinel {
+            loop {
+                if let Err(err) = Self::watch(sentinel) {
+                    warn!("store sentinel watch dropped, reconnecting: {}", err);
+                }
+
+                // Hold before reconnecting (prevents reconnect loop floods)
+                thread::sleep(SENTINEL_RECONNECT_INTERVAL);
+            }
+        }
+    }
+
+    pub fn discover(
+        sentinel: &ConfigRedisSentinel,
+    ) -> Result<(StoreSentinelTarget, Vec<StoreSentinelTarget>), &'static str> {
+        // Ask sentinels in order, until one answers
+        for address in &sentinel.addresses {
+            debug!("discovering store master from sentinel at: {}", address);
+
+            match Self::connect(address).and_then(|mut connection| {
+                Self::discover_from(&mut connection, &sentinel.master_name)
+            }) {
+                Ok((master, replicas)) => {
+                    info!(
+                        "discovered store master at {}:{} with {} replicas from sentinel at: {}",
+                        master.0,
+                        master.1,
+                        replicas.len(),
+                        address
+                    );
+
+                    return Ok((master, replicas));
+                }
+                Err(err) => {
+                    warn!(
+                        "could not discover store master from sentinel at: {}: {}",
+                        address, err
+                    );
+                }
+            }
+        }
+
+        Err("could not discover store master from any sentinel")
+    }
+
+    fn discover_from(
+        connection: &mut Connection,
+        master_name: &str,
+    ) -> Result<(StoreSentinelTarget, Vec<StoreSentinelTarget>), RedisError> {
+        let master = redis::cmd("SENTINEL")
+            .arg("get-master-addr-by-name")
+            .arg(master_name)
+            .query::<StoreSentinelTarget>(connection)?;
+
+        // Notice: the 'slaves' sub-command is used over 'replicas', as it is supported by all \
+        //   Sentinel versions.
+        let replicas = redis::cmd("SENTINEL")
+            .arg("slaves")
+            .arg(master_name)
+            .query::<Vec<HashMap<String, String>>>(connection)?
+            .into_iter()
+            .filter_map(|replica| {
+                // Only keep healthy replicas
+                let is_down = replica
+                    .get("flags")
+                    .map(|flags| {
+                        flags.split(",").any(|flag| {
+                            flag == "s_down" || flag == "o_down" || flag == "disconnected"
+                        })
+                    })
+                    .unwrap_or(true);
+
+                match (is_down, replica.get("ip"), replica.get("port")) {
+                    (false, Some(ip), Some(port)) => {
+                        port.parse::<u16>().ok().map(|port| (ip.to_owned(), port))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Ok((master, replicas))
+    }
+
+    fn watch(sentinel: &ConfigRedisSentinel) -> Result<(), RedisError> {
+        // Connect to first reachable sentinel
+        let mut connection = sentinel
+            .addresses
+            .iter()
+            .filter_map(|address| Self::connect(address).ok())
+            .next()
+            .ok_or_else(|| {
+                RedisError::from((redis::ErrorKind::IoError, "no sentinel is reachable"))
+            })?;
+
+        // Master may have switched while not watching, thus rebind if needed
+        Self::rebind_if_switched(sentinel);
+
+        let mut pubsub = connection.as_pubsub();
+
+        pubsub.set_read_timeout(Some(SENTINEL_READ_TIMEOUT))?;
+        pubsub.subscribe(SENTINEL_CHANNEL_SWITCH_MASTER)?;
+
+        info!("watching for store master switches from sentinel");
+
+        loop {
+            match pubsub.get_message() {
+                Ok(message) => {
+                    // Payload format: '<master name> <old ip> <old port> <new ip> <new port>'
+                    let payload: String = message.get_payload()?;
+
+                    if payload.split(" ").next() == Some(sentinel.master_name.as_str()) {
+                        info!("got store master switch from sentinel: {}", payload);
+
+                        if let Err(err) = Self::rebind() {
+                            error!("could not rebind to store after master switch: {}", err);
+                        }
+                    }
+                }
+                Err(ref err) if err.is_timeout() == true => {
+                    // No master switch in a while; this is expected, keep watching
+                    debug!("no store master switch received, still watching");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn rebind_if_switched(sentinel: &ConfigRedisSentinel) {
+        if let (Ok((master, _)), Some(master_info)) = (
+            Self::discover(sentinel),
+            APP_STORE
+                .remote()
+                .and_then(|remote| remote.master_connection_info()),
+        ) {
+            if master_info.addr.to_string() != format!("{}:{}", master.0, master.1) {
+                info!("store master changed while not watching, rebinding");
+
+                if let Err(err) = Self::rebind() {
+                    error!("could not rebind to store after master change: {}", err);
+                }
+            }
+        }
+    }
+
+    fn rebind() -> Result<(), &'static str> {
+        match APP_STORE.remote() {
+            Some(remote) => remote.rebind(),
+            None => Err("store backend does not support rebinding"),
+        }
+    }
+
+    fn connect(address: &str) -> Result<Connection, RedisError> {
+        let client = Client::open(format!("redis://{}/", address).as_str())?;
+
+        client.get_connection_with_timeout(Duration::from_secs(
+            APP_CONF.redis.connection_timeout_seconds,
+        ))
+    }
+}
@@ -4,15 +4,18 @@
 // Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
 // License: Mozilla Public License v2.0 (MPL v2.0)
 
-use r2d2::Pool;
-use r2d2_redis::RedisConnectionManager;
-use redis::{Commands, ErrorKind, RedisError};
-use serde_json::{self, Error as SerdeJSONError};
+use rand;
+use redis::RedisError;
+use serde_json::Error as SerdeJSONError;
+use sled::Error as SledError;
 use std::collections::HashSet;
-use std::time::{Duration, SystemTime};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::cache::STORE_CACHE;
-use super::key::StoreKey;
+use super::embedded::StoreEmbeddedBuilder;
+use super::memory::StoreMemoryBuilder;
+use super::remote::{StoreRemote, StoreRemoteBuilder};
+use crate::config::config::ConfigStoreBackend;
 use crate::dns::record::{
     RecordBlackhole, RecordName, RecordRegions, RecordType, RecordValue, RecordValues,
 };
@@ -20,33 +23,13 @@ use crate::dns::zone::ZoneName;
 
 use crate::APP_CONF;
 
-static KEY_TYPE: &'static str = "t";
-static KEY_NAME: &'static str = "n";
-static KEY_TTL: &'static str = "e";
-static KEY_BLACKHOLE: &'static str = "b";
-static KEY_REGION: &'static str = "r";
-static KEY_RESCUE: &'static str = "f"; // Alias for 'failover'
-static KEY_VALUE: &'static str = "v";
-
-type StoreGetType = (
-    String,
-    String,
-    u32,
-    Option<String>,
-    Option<String>,
-    Option<String>,
-    String,
-);
-
-type StorePoolType = (Pool<RedisConnectionManager>, String);
+pub type Store = Box<dyn RecordStore + Send + Sync>;
 
-pub struct StoreBuilder;
+pub static VERSION_DEFAULT: &'static str = "0";
 
-pub struct Store {
-    pools: Vec<StorePoolType>,
-}
+pub struct StoreBuilder;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StoreRecord {
     pub kind: RecordType,
     pub name: RecordName,
@@ -55,331 +38,195 @@ pub struct StoreRecord {
     pub regions: Option<RecordRegions>,
     pub rescue: Option<RecordValues>,
     pub values: RecordValues,
+    pub version: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct StoreIndexEntry {
+    pub name: RecordName,
+    pub kind: RecordType,
+}
+
+pub enum StoreBatchOperation {
+    Set(StoreRecord),
+    Remove(RecordName, RecordType),
+}
+
+#[derive(Debug, Clone)]
+pub struct StoreChange {
+    pub name: RecordName,
+    pub kind: RecordType,
+    pub before: Option<StoreRecord>,
+    pub after: Option<StoreRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoreRevision {
+    pub revision: u64,
+    pub timestamp: u64,
+    pub identity: Option<String>,
+    pub label: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub record: Option<StoreRecord>,
+}
+
+#[derive(Default, Clone)]
+pub struct StoreActor {
+    pub identity: Option<String>,
+    pub label: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub proxied_ip: Option<IpAddr>,
+}
+
+#[derive(Clone)]
+pub struct StoreCondition {
+    pub name: RecordName,
+    pub kind: RecordType,
+    pub expect: StoreConditionExpect,
+}
+
+#[derive(Clone)]
+pub enum StoreConditionExpect {
+    Exists,
+    Missing,
+    Versions(Vec<String>),
+    NotVersions(Vec<String>),
 }
 
+#[derive(Debug)]
 pub enum StoreError {
+    Conflict,
     Corrupted,
     Encoding(SerdeJSONError),
     Connector(RedisError),
+    Embedded(SledError),
     NotFound,
     Disconnected,
 }
 
-impl StoreBuilder {
-    pub fn new() -> Store {
-        let mut pools = Vec::new();
-
-        // Bind to master pool
-        Self::pool_bind(
-            &mut pools,
-            &APP_CONF.redis.master.host,
-            APP_CONF.redis.master.port,
-            &APP_CONF.redis.master.password,
-        );
-
-        // Bind to rescue pools (if any)
-        if let Some(ref rescue_items) = APP_CONF.redis.rescue {
-            for rescue in rescue_items {
-                Self::pool_bind(&mut pools, &rescue.host, rescue.port, &rescue.password);
-            }
-        }
-
-        Store { pools: pools }
-    }
-
-    fn pool_bind(pools: &mut Vec<StorePoolType>, host: &str, port: u16, password: &Option<String>) {
-        // Establish pool connection for this Redis target
-        match Self::pool_connect(host, port, password) {
-            Ok(master_pool) => pools.push(master_pool),
-            Err(err) => panic!(err),
-        }
-    }
+pub trait RecordStore {
+    fn check(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<(), StoreError>;
 
-    fn pool_connect(
-        host: &str,
-        port: u16,
-        password: &Option<String>,
-    ) -> Result<StorePoolType, &'static str> {
-        info!("binding to store backend at {}:{}", host, port);
-
-        let addr_auth = match password {
-            Some(ref password) => format!(":{}@", password),
-            None => "".to_string(),
-        };
-
-        let tcp_addr_raw = format!(
-            "redis://{}{}:{}/{}",
-            &addr_auth, host, port, APP_CONF.redis.database,
-        );
-
-        debug!("will connect to redis at: {}", tcp_addr_raw);
-
-        match RedisConnectionManager::new(tcp_addr_raw.as_ref()) {
-            Ok(manager) => {
-                let builder = Pool::builder()
-                    .test_on_check_out(true)
-                    .max_size(APP_CONF.redis.pool_size)
-                    .max_lifetime(Some(Duration::from_secs(
-                        APP_CONF.redis.max_lifetime_seconds,
-                    )))
-                    .idle_timeout(Some(Duration::from_secs(
-                        APP_CONF.redis.idle_timeout_seconds,
-                    )))
-                    .connection_timeout(Duration::from_secs(
-                        APP_CONF.redis.connection_timeout_seconds,
-                    ));
-
-                match builder.build(manager) {
-                    Ok(pool) => {
-                        info!("connected to redis at: {}", tcp_addr_raw);
-
-                        Ok((pool, tcp_addr_raw))
-                    }
-                    Err(_) => Err("could not spawn redis pool"),
-                }
-            }
-            Err(_) => Err("could not create redis connection manager"),
-        }
+    fn get(
+        &self,
+        zone_name: &ZoneName,
+        record_name: &RecordName,
+        record_type: &RecordType,
+    ) -> Result<StoreRecord, StoreError>;
+
+    fn set(&self, zone_name: &ZoneName, record: StoreRecord) -> Result<(), StoreError> {
+        self.batch(
+            zone_name,
+            vec![StoreBatchOperation::Set(record)],
+            Vec::new(),
+            &StoreActor::default(),
+        )
+        .map(|_| ())
     }
-}
 
-impl Store {
-    pub fn check(
+    fn remove(
         &self,
         zone_name: &ZoneName,
         record_name: &RecordName,
         record_type: &RecordType,
     ) -> Result<(), StoreError> {
-        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
-
-        // Check from local cache?
-        if STORE_CACHE.has(&store_key) == true {
-            return Ok(());
-        }
-
-        // Check from store
-        get_cache_store_client!(&self.pools, StoreError::Disconnected, client {
-            client.exists::<&str, bool>(&store_key)
-            .map_err(|err| {
-                StoreError::Connector(err)
-            })
-            .and_then(|exists| {
-                if exists == true {
-                    Ok(())
-                } else {
-                    // Store in local cache (no value)
-                    STORE_CACHE.push(&store_key, None, None);
-
-                    // Consider as not found
-                    Err(StoreError::NotFound)
-                }
-            })
-        })
+        self.batch(
+            zone_name,
+            vec![StoreBatchOperation::Remove(
+                record_name.to_owned(),
+                record_type.to_owned(),
+            )],
+            Vec::new(),
+            &StoreActor::default(),
+        )
+        .map(|_| ())
     }
 
-    pub fn get(
+    // Notice: operations are applied atomically, ie. either all or none of them get applied. \
+    //   If any condition does not hold, nothing gets applied and a conflict error is returned. \
+    //   The previous state of each changed record is appended to its history, on behalf of \
+    //   the given actor. One change is returned for each operation (removing a non-existing \
+    //   record holds no record before and after the change).
+    fn batch(
+        &self,
+        zone_name: &ZoneName,
+        operations: Vec<StoreBatchOperation>,
+        conditions: Vec<StoreCondition>,
+        actor: &StoreActor,
+    ) -> Result<Vec<StoreChange>, StoreError>;
+
+    // Notice: revisions are returned from the most recent to the oldest one.
+    fn history(
         &self,
         zone_name: &ZoneName,
         record_name: &RecordName,
         record_type: &RecordType,
-    ) -> Result<StoreRecord, StoreError> {
-        let store_key = StoreKey::to_key(zone_name, record_name, record_type);
-
-        // Get from local cache?
-        if let Ok(cached_records) = STORE_CACHE.get(&store_key) {
-            return match cached_records {
-                Some(cached_records) => Ok(cached_records),
-                None => Err(StoreError::NotFound),
-            };
-        }
+    ) -> Result<Vec<StoreRevision>, StoreError>;
 
-        // Get from store
-        self.raw_get_remote(&store_key, None)
-    }
+    fn list(&self, zone_name: &ZoneName) -> Result<Vec<StoreRecord>, StoreError>;
 
-    pub fn set(&self, zone_name: &ZoneName, record: StoreRecord) -> Result<(), StoreError> {
-        get_cache_store_client!(&self.pools, StoreError::Disconnected, client {
-            let blackhole_encoder = match record.blackhole {
-                Some(ref blackhole) => {
-                    if blackhole.has_items() == true {
-                        serde_json::to_string(blackhole)
-                    } else {
-                        Ok("".to_owned())
-                    }
-                },
-                None => Ok("".to_owned())
-            };
-            let region_encoder = match record.regions {
-                Some(ref regions) => serde_json::to_string(regions),
-                None => Ok("".to_owned())
-            };
-            let rescue_encoder = match record.rescue {
-                Some(ref rescue) => {
-                    if rescue.is_empty() == false {
-                        serde_json::to_string(rescue)
-                    } else {
-                        Ok("".to_owned())
-                    }
-                },
-                None => Ok("".to_owned())
-            };
-
-            match (
-                serde_json::to_string(&record.values),
-                blackhole_encoder,
-                region_encoder,
-                rescue_encoder
-            ) {
-                (Ok(values), Ok(blackhole), Ok(regions), Ok(rescue)) => {
-                    let store_key = StoreKey::to_key(zone_name, &record.name, &record.kind);
-
-                    // Clean from local cache
-                    STORE_CACHE.pop(&store_key);
-
-                    // Store in remote
-                    client.hset_multiple(
-                        store_key, &[
-                            (KEY_TYPE, record.kind.to_str()),
-                            (KEY_NAME, record.name.to_str()),
-                            (KEY_TTL, &record.ttl.unwrap_or(0).to_string()),
-                            (KEY_BLACKHOLE, &blackhole),
-                            (KEY_REGION, &regions),
-                            (KEY_RESCUE, &rescue),
-                            (KEY_VALUE, &values),
-                        ]
-                    ).map_err(|err| {
-                        StoreError::Connector(err)
-                    })
-                },
-                (Err(err), _, _, _) |
-                (_, Err(err), _, _) |
-                (_, _, Err(err), _) |
-                (_, _, _, Err(err)) => {
-                    Err(StoreError::Encoding(err))
-                }
-            }
-        })
-    }
+    // Notice: index entries are returned sorted by record name, then by record type.
+    fn index(
+        &self,
+        zone_name: &ZoneName,
+        name_prefix: Option<&str>,
+    ) -> Result<Vec<StoreIndexEntry>, StoreError>;
 
-    pub fn remove(
+    fn get_fresh(
         &self,
         zone_name: &ZoneName,
         record_name: &RecordName,
         record_type: &RecordType,
-    ) -> Result<(), StoreError> {
-        get_cache_store_client!(&self.pools, StoreError::Disconnected, client {
-            let store_key = StoreKey::to_key(zone_name, record_name, record_type);
-
-            // Clean from local cache
-            STORE_CACHE.pop(&store_key);
-
-            // Delete from remote
-            client.del(store_key).map_err(|err| {
-                StoreError::Connector(err)
-            })
-        })
+    ) -> Result<StoreRecord, StoreError> {
+        // Notice: only backends with a local cache can serve outdated records from 'get()'
+        self.get(zone_name, record_name, record_type)
     }
 
-    pub fn raw_get_remote(
+    fn get_stale(
         &self,
-        store_key: &str,
-        cache_accessed_at: Option<SystemTime>,
+        _zone_name: &ZoneName,
+        _record_name: &RecordName,
+        _record_type: &RecordType,
     ) -> Result<StoreRecord, StoreError> {
-        get_cache_store_client!(&self.pools, StoreError::Disconnected, client {
-            match client.hget::<_, _, StoreGetType>(
-                store_key,
-                (KEY_TYPE, KEY_NAME, KEY_TTL, KEY_BLACKHOLE, KEY_REGION, KEY_RESCUE, KEY_VALUE),
-            ) {
-                Ok(values) => {
-                    if let (Some(kind_value), Some(name_value), Ok(value_value)) = (
-                        RecordType::from_str(&values.0),
-                        RecordName::from_str(&values.1),
-                        serde_json::from_str(&values.6)
-                    ) {
-                        let ttl = if values.2 > 0 {
-                            Some(values.2)
-                        } else {
-                            None
-                        };
-
-                        let blackhole = values.3.and_then(|blackhole_raw| {
-                            serde_json::from_str::<RecordBlackhole>(&blackhole_raw).ok()
-                        });
-                        let regions = values.4.and_then(|region_raw| {
-                            serde_json::from_str::<RecordRegions>(&region_raw).ok()
-                        });
-                        let rescue = values.5.and_then(|rescue_raw| {
-                            serde_json::from_str::<RecordValues>(&rescue_raw).ok()
-                        });
-
-                        debug!(
-                            "read store record with kind: {:?}, name: {:?} and values: {:?}",
-                            kind_value,
-                            name_value,
-                            value_value
-                        );
-
-                        if blackhole.is_some() == true {
-                            debug!(
-                                "store record with kind: {:?}, name: {:?} has blackhole: {:?}",
-                                kind_value,
-                                name_value,
-                                blackhole
-                            );
-                        }
-                        if regions.is_some() == true {
-                            debug!(
-                                "store record with kind: {:?}, name: {:?} has regions: {:?}",
-                                kind_value,
-                                name_value,
-                                regions
-                            );
-                        }
-                        if rescue.is_some() == true {
-                             debug!(
-                                "store record with kind: {:?}, name: {:?} has rescue: {:?}",
-                                kind_value,
-                                name_value,
-                                rescue
-                            );
-                        }
-
-                        let record = StoreRecord {
-                            kind: kind_value,
-                            name: name_value,
-                            ttl: ttl,
-                            blackhole: blackhole,
-                            regions: regions,
-                            rescue: rescue,
-                            values: value_value,
-                        };
-
-                        // Store in local cache
-                        STORE_CACHE.push(store_key, Some(record.clone()), cache_accessed_at);
-
-                        Ok(record)
-                    } else {
-                        Err(StoreError::Corrupted)
-                    }
-                },
-                Err(err) => {
-                    // Store in local cache? (no value)
-                    // Notice: do not store an empty cache if error is not a type error (meaning: \
-                    //   no such value exist; this avoids storing a blank cache entry for I/O \
-                    //   and network timeout errors, which would corrupt the cache)
-                    if err.kind() == ErrorKind::TypeError {
-                        STORE_CACHE.push(store_key, None, cache_accessed_at);
-                    }
-
-                    // Consider as not found
-                    Err(StoreError::NotFound)
-                },
-            }
-        })
+        // Notice: only backends with a local cache can serve stale records
+        Err(StoreError::Disconnected)
+    }
+
+    fn remote(&self) -> Option<&StoreRemote> {
+        None
+    }
+}
+
+impl StoreBuilder {
+    pub fn new() -> Store {
+        match APP_CONF.store.backend {
+            ConfigStoreBackend::Redis => Box::new(StoreRemoteBuilder::new()),
+            ConfigStoreBackend::Embedded => Box::new(StoreEmbeddedBuilder::new()),
+            ConfigStoreBackend::Memory => Box::new(StoreMemoryBuilder::new()),
+        }
     }
 }
 
 impl StoreRecord {
+    pub fn to_version(&self) -> &str {
+        // Notice: records written before versions were introduced have no version
+        self.version
+            .as_ref()
+            .map(|version| version.as_str())
+            .unwrap_or(VERSION_DEFAULT)
+    }
+
+    pub fn renew_version(&mut self) {
+        // Notice: versions are random (rather than incremented), so that a record that gets \
+        //   removed then created again never reuses a version seen before.
+        self.version = Some(format!("{:016x}", rand::random::<u64>()));
+    }
+
     pub fn list_record_values<'a>(&'a self) -> HashSet<&'a RecordValue> {
         let mut unique_values = HashSet::new();
 
@@ -423,3 +270,85 @@ impl StoreRecord {
         }
     }
 }
+
+impl StoreIndexEntry {
+    pub fn from_member(member: &str) -> Option<StoreIndexEntry> {
+        // Notice: record names cannot contain ':', thus the last ':' always separates the record \
+        //   name from the record type.
+        let mut parts = member.rsplitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(kind), Some(name)) => {
+                if let (Some(name), Some(kind)) =
+                    (RecordName::from_str(name), RecordType::from_str(kind))
+                {
+                    Some(StoreIndexEntry {
+                        name: name,
+                        kind: kind,
+                    })
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    pub fn from_records<'a, I>(records: I, name_prefix: Option<&str>) -> Vec<StoreIndexEntry>
+    where
+        I: Iterator<Item = &'a StoreRecord>,
+    {
+        let mut entries = records
+            .filter(|record| {
+                name_prefix
+                    .map(|name_prefix| record.name.to_str().starts_with(name_prefix))
+                    .unwrap_or(true)
+            })
+            .map(|record| StoreIndexEntry {
+                name: record.name.to_owned(),
+                kind: record.kind.to_owned(),
+            })
+            .collect::<Vec<StoreIndexEntry>>();
+
+        entries.sort_by(|entry_a, entry_b| entry_a.to_member().cmp(&entry_b.to_member()));
+
+        entries
+    }
+
+    pub fn to_member(&self) -> String {
+        format!("{}:{}", self.name.to_str(), self.kind.to_str())
+    }
+}
+
+impl StoreActor {
+    pub fn to_revision(&self, revision: u64, record: Option<StoreRecord>) -> StoreRevision {
+        StoreRevision {
+            revision: revision,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            identity: self.identity.to_owned(),
+            label: self.label.to_owned(),
+            ip: self.ip,
+            record: record,
+        }
+    }
+}
+
+impl StoreConditionExpect {
+    pub fn holds(&self, version: Option<&str>) -> bool {
+        match (self, version) {
+            (StoreConditionExpect::Exists, version) => version.is_some(),
+            (StoreConditionExpect::Missing, version) => version.is_none(),
+            (StoreConditionExpect::Versions(versions), Some(version)) => {
+                versions.iter().any(|expected| expected == version)
+            }
+            (StoreConditionExpect::Versions(_), None) => false,
+            (StoreConditionExpect::NotVersions(versions), Some(version)) => {
+                versions.iter().all(|expected| expected != version)
+            }
+            (StoreConditionExpect::NotVersions(_), None) => true,
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Constellation
+//
+// Pluggable authoritative DNS server
+// Copyright: 2018, Valerian Saliou <valerian@valeriansaliou.name>
+// License: Mozilla Public License v2.0 (MPL v2.0)
+
+use std::time::{Duration, Instant};
+
+use crate::dns::zone::ZoneName;
+use crate::APP_CONF;
+use crate::APP_STORE;
+
+pub struct StoreWarmupBuilder;
+pub struct StoreWarmup;
+
+impl StoreWarmupBuilder {
+    pub fn new() -> StoreWarmup {
+        StoreWarmup {}
+    }
+}
+
+impl StoreWarmup {
+    pub fn run(&self) {
+        let remote = match APP_STORE.remote() {
+            Some(remote) => remote,
+            None => {
+                warn!("store cache warm-up is only supported with the redis backend, skipping");
+
+                return;
+            }
+        };
+
+        info!("warming up store cache...");
+
+        let warmup_start = Instant::now();
+        let deadline = warmup_start + Duration::from_secs(APP_CONF.redis.warmup_timeout_seconds);
+
+        let mut count_loaded = 0;
+
+        for zone_name_raw in APP_CONF.dns.zone.keys() {
+            if let Some(zone_name) = ZoneName::from_str(zone_name_raw) {
+                match remote.warmup(&zone_name, deadline) {
+                    Ok(count) => {
+                        debug!(
+                            "warmed up store cache for zone: {} (count: {})",
+                            zone_name_raw, count
+                        );
+
+                        count_loaded += count;
+                    }
+                    Err(_) => {
+                        warn!("could not warm up store cache for zone: {}", zone_name_raw);
+                    }
+                }
+            }
+
+            // Deadline reached? Stop there, as DNS queries must be served
+            if Instant::now() >= deadline {
+                warn!("store cache warm-up timed out, proceeding with a partial cache");
+
+                break;
+            }
+        }
+
+        let warmup_took = warmup_start.elapsed();
+
+        info!(
+            "warmed up store cache with {} records (took {}s + {}ms)",
+            count_loaded,
+            warmup_took.as_secs(),
+            warmup_took.subsec_millis()
+        );
+    }
+}